@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::kv::KvObject;
+use crate::util;
+
+/// Held while addonlist.txt is being read and written back, so concurrent changes don't overwrite each other
+static LIST_LOCK: Mutex<()> = Mutex::new(());
+
+/// How addons are enabled and disabled
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ToggleMode {
+    /// Renames the file to .vpk.disabled, so the game can't see it
+    #[default]
+    Rename,
+    /// Sets the addon's state in left4dead2/addonlist.txt, like the game's addons menu does
+    AddonList
+}
+
+/// The game's left4dead2/addonlist.txt, which stores the enabled state of every addon it knows of:
+/// ```text
+/// "AddonList"
+/// {
+///     "123456.vpk"    "1"
+///     "workshop\123456.vpk"    "0"
+/// }
+/// ```
+pub struct AddonList {
+    path: PathBuf,
+    entries: BTreeMap<String, String>
+}
+
+impl AddonList {
+    /// Loads addonlist.txt for the addons folder, an empty list is returned if it doesn't exist yet
+    pub fn load(addons_dir: &Path) -> Result<Self, String> {
+        let path = addons_dir.parent()
+            .ok_or_else(|| "Addons folder has no parent folder".to_string())?
+            .join("addonlist.txt");
+        if !path.exists() {
+            return Ok(Self { path, entries: BTreeMap::new() })
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())?;
+        // Read with the lenient reader, as keyvalues_serde treats the backslash in "workshop\123.vpk" as an escape
+        let root = KvObject::parse(&content);
+        let list = root.get_obj("AddonList")
+            .ok_or_else(|| "failed to parse addonlist.txt: no AddonList object".to_string())?;
+        let entries = list.entries()
+            .filter_map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
+            .collect();
+        Ok(Self { path, entries })
+    }
+
+    /// Writes the list like the game does, with keys written as-is and no escaping
+    pub fn save(&self) -> Result<(), String> {
+        let mut content = String::from("\"AddonList\"\n{\n");
+        for (key, value) in &self.entries {
+            content.push_str(&format!("\t\"{}\"\t\t\"{}\"\n", key, value));
+        }
+        content.push_str("}\n");
+        debug!("saving addonlist to {:?}", self.path);
+        std::fs::write(&self.path, content)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Enabled state of the addon, None if the game has no entry for it. Keys are matched case-insensitively, like windows paths
+    pub fn get(&self, key: &str) -> Option<bool> {
+        self.entries.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.trim() != "0")
+    }
+
+    pub fn set(&mut self, key: &str, enabled: bool) {
+        let value = if enabled { "1" } else { "0" }.to_string();
+        match self.entries.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            Some((_, v)) => *v = value,
+            None => { self.entries.insert(key.to_string(), value); }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|k, _| !k.eq_ignore_ascii_case(key));
+    }
+}
+
+/// Loads addonlist.txt, applies the changes and saves it
+pub fn update<T>(addons_dir: &Path, f: impl FnOnce(&mut AddonList) -> Result<T, String>) -> Result<T, String> {
+    let _lock = LIST_LOCK.lock().unwrap();
+    let mut list = AddonList::load(addons_dir)?;
+    let result = f(&mut list)?;
+    list.save()?;
+    Ok(result)
+}
+
+/// The addons folder that a folder of addons belongs to, skipping over the workshop folder
+pub fn get_addons_dir(folder: &Path) -> &Path {
+    match folder.parent() {
+        Some(parent) if folder.file_name().is_some_and(|name| name == "workshop") => parent,
+        _ => folder
+    }
+}
+
+/// Loads addonlist.txt for the folder of addons, logging any errors
+pub fn load_for_folder(folder: &Path) -> Option<AddonList> {
+    match AddonList::load(get_addons_dir(folder)) {
+        Ok(list) => Some(list),
+        Err(e) => {
+            warn!("Could not load addonlist.txt for {:?}: {}", folder, e);
+            None
+        }
+    }
+}
+
+/// The addon's key in addonlist.txt: its path relative to the addons folder, without any .disabled
+pub fn get_list_key(addon_path: &Path) -> Option<String> {
+    let addons_dir = get_addons_dir(addon_path.parent()?);
+    let relative = addon_path.strip_prefix(addons_dir).ok()?;
+    let key = relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("\\");
+    Some(key.strip_suffix(".disabled").map(|k| k.to_string()).unwrap_or(key))
+}
+
+/// Is the file named so the game can load it
+pub fn is_file_enabled(addon_path: &Path) -> bool {
+    !addon_path.to_string_lossy().ends_with(".disabled")
+}
+
+/// What the game sees for the addon
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddonState {
+    /// Will the game load the addon
+    pub enabled: bool,
+    /// File is not renamed to .disabled
+    pub file_enabled: bool,
+    /// State in addonlist.txt, None if it's not listed
+    pub list_enabled: Option<bool>,
+    /// File and addonlist.txt disagree on if the addon is enabled
+    pub mismatch: bool
+}
+
+pub fn get_addon_state(list: Option<&AddonList>, addon_path: &Path) -> AddonState {
+    let file_enabled = is_file_enabled(addon_path);
+    let list_enabled = list.zip(get_list_key(addon_path))
+        .and_then(|(list, key)| list.get(&key));
+    AddonState {
+        // The game enables addons it hasn't seen before
+        enabled: file_enabled && list_enabled.unwrap_or(true),
+        file_enabled,
+        list_enabled,
+        mismatch: list_enabled.is_some_and(|list_enabled| list_enabled != file_enabled)
+    }
+}
+
+/// Enables or disables the addon, returning its new path.
+/// With ToggleMode::AddonList, addons disabled by file name are renamed back first so the list decides their state
+pub fn toggle(path: &Path, mode: ToggleMode) -> Result<PathBuf, String> {
+    if mode == ToggleMode::Rename {
+        let new_path = util::get_toggled_path(path)?;
+        debug!("toggle_addon {:?} -> {:?}", path, &new_path);
+        std::fs::rename(path, &new_path).map_err(|e| e.to_string())?;
+        return Ok(new_path)
+    }
+    let folder = path.parent().ok_or_else(|| "Addon has no parent folder".to_string())?;
+    update(get_addons_dir(folder), |list| {
+        let enabled = !get_addon_state(Some(list), path).enabled;
+        let mut path = path.to_path_buf();
+        if !is_file_enabled(&path) {
+            let new_path = util::get_toggled_path(&path)?;
+            debug!("toggle_addon {:?} -> {:?}", &path, &new_path);
+            std::fs::rename(&path, &new_path).map_err(|e| e.to_string())?;
+            path = new_path;
+        }
+        let key = get_list_key(&path)
+            .ok_or_else(|| format!("Could not find addonlist.txt entry for {:?}", path))?;
+        debug!("toggle_addon addonlist {} = {}", key, enabled);
+        list.set(&key, enabled);
+        Ok(path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::AddonList;
+
+    const ADDONLIST: &str = include_str!("../tests/fixtures/addonlist/addonlist.txt");
+
+    /// Creates left4dead2/addons in a fresh folder, with addonlist.txt next to it
+    fn game_dir(name: &str, addonlist: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        let addons_dir = dir.join("addons");
+        std::fs::create_dir_all(addons_dir.join("workshop")).unwrap();
+        std::fs::write(dir.join("addonlist.txt"), addonlist).unwrap();
+        addons_dir
+    }
+
+    #[test]
+    fn reads_backslash_keys() {
+        let addons_dir = game_dir("addonlist_read", ADDONLIST);
+        let list = AddonList::load(&addons_dir).unwrap();
+        assert_eq!(list.get("workshop\\1234567890.vpk"), Some(true));
+        assert_eq!(list.get("workshop\\2345678901.vpk"), Some(false));
+        assert_eq!(list.get("WORKSHOP\\1234567890.VPK"), Some(true));
+        assert_eq!(list.get("my_campaign.vpk"), Some(true));
+        assert_eq!(list.get("old_hud.vpk"), Some(false));
+        assert_eq!(list.get("workshop\\999.vpk"), None);
+    }
+
+    #[test]
+    fn round_trip_keeps_keys() {
+        let addons_dir = game_dir("addonlist_round_trip", ADDONLIST);
+        let mut list = AddonList::load(&addons_dir).unwrap();
+        list.save().unwrap();
+        let saved = std::fs::read_to_string(addons_dir.parent().unwrap().join("addonlist.txt")).unwrap();
+        assert!(saved.contains("\"workshop\\1234567890.vpk\""), "{}", saved);
+        assert!(!saved.contains("\\\\"), "{}", saved);
+        assert_eq!(AddonList::load(&addons_dir).unwrap().entries, list.entries);
+
+        list.set("workshop\\2345678901.vpk", true);
+        list.set("workshop\\3456789012.vpk", false);
+        list.remove("old_hud.vpk");
+        list.save().unwrap();
+        let list = AddonList::load(&addons_dir).unwrap();
+        assert_eq!(list.get("workshop\\2345678901.vpk"), Some(true));
+        assert_eq!(list.get("workshop\\3456789012.vpk"), Some(false));
+        assert_eq!(list.get("old_hud.vpk"), None);
+        assert_eq!(list.entries.len(), 4);
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// Most an archive may extract to in total, so a decompression bomb can't fill the disk
+const MAX_EXTRACTED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+/// Zip entries that claim to compress better than this are assumed to be decompression bombs
+const MAX_COMPRESSION_RATIO: u64 = 200;
+
+/// A file in an archive that was not installed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SkippedFile {
+    /// Path of the file inside the archive
+    pub name: String,
+    pub reason: String
+}
+
+#[derive(Default)]
+pub struct Extracted {
+    pub files: Vec<PathBuf>,
+    pub skipped: Vec<SkippedFile>
+}
+
+impl Extracted {
+    fn skip(&mut self, name: &str, reason: impl Into<String>) {
+        let reason = reason.into();
+        debug!("skipping {} in archive: {}", name, reason);
+        self.skipped.push(SkippedFile { name: name.to_string(), reason });
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ext == "zip" || ext == "7z" || ext == "rar")
+}
+
+/// The file name an archive entry should be extracted as, None if its path tries to escape the folder it's extracted to.
+/// Only the file name is kept, as addons are always installed to the top of the addons folder
+fn get_safe_file_name(entry_name: &str) -> Option<String> {
+    let entry_name = entry_name.replace('\\', "/");
+    if entry_name.starts_with('/') || entry_name.contains(':') {
+        return None
+    }
+    let mut parts = entry_name.split('/').filter(|part| !part.is_empty() && *part != ".");
+    if parts.clone().any(|part| part == "..") {
+        return None
+    }
+    parts.next_back().map(|name| name.to_string())
+}
+
+/// Checks the entry is a .vpk that is safe to extract, returning the path to extract it to
+fn get_entry_dest(extracted: &mut Extracted, dest_dir: &Path, entry_name: &str) -> Option<PathBuf> {
+    let Some(file_name) = get_safe_file_name(entry_name) else {
+        extracted.skip(entry_name, "Path leaves the archive");
+        return None
+    };
+    if !file_name.to_lowercase().ends_with(".vpk") {
+        extracted.skip(entry_name, "Not a .vpk file");
+        return None
+    }
+    let dest = dest_dir.join(&file_name);
+    if dest.exists() {
+        extracted.skip(entry_name, format!("Archive has more than one {}", file_name));
+        return None
+    }
+    Some(dest)
+}
+
+/// Writes out the entry, stopping once more than the remaining budget has been read
+fn extract_entry(reader: &mut dyn Read, dest: &Path, budget: &mut u64) -> Result<(), String> {
+    let mut file = File::create(dest).map_err(|e| e.to_string())?;
+    let written = std::io::copy(&mut reader.take(*budget + 1), &mut file)
+        .map_err(|e| e.to_string())?;
+    if written > *budget {
+        drop(file);
+        std::fs::remove_file(dest).ok();
+        return Err("Archive extracts to more than the size limit".to_string())
+    }
+    *budget -= written;
+    Ok(())
+}
+
+fn extract_zip(archive: &Path, dest_dir: &Path, mut budget: u64) -> Result<Extracted, String> {
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    let mut zip = zip::ZipArchive::new(file)
+        .map_err(|e| format!("Could not open zip: {}", e))?;
+    let mut extracted = Extracted::default();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| format!("Could not read zip: {}", e))?;
+        if entry.is_dir() { continue; }
+        let name = entry.name().to_string();
+        let Some(dest) = get_entry_dest(&mut extracted, dest_dir, &name) else { continue; };
+        if entry.size() > budget {
+            extracted.skip(&name, "File is larger than the size limit");
+            continue;
+        }
+        if entry.size() / entry.compressed_size().max(1) > MAX_COMPRESSION_RATIO {
+            extracted.skip(&name, "File is compressed suspiciously well");
+            continue;
+        }
+        match extract_entry(&mut entry, &dest, &mut budget) {
+            Ok(_) => extracted.files.push(dest),
+            Err(e) => extracted.skip(&name, e)
+        }
+    }
+    Ok(extracted)
+}
+
+fn extract_7z(archive: &Path, dest_dir: &Path, mut budget: u64) -> Result<Extracted, String> {
+    let mut reader = sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+        .map_err(|e| format!("Could not open 7z: {}", e))?;
+    let mut extracted = Extracted::default();
+    reader.for_each_entries(|entry, entry_reader| {
+        if entry.is_directory() { return Ok(true) }
+        let name = entry.name().to_string();
+        let dest = get_entry_dest(&mut extracted, dest_dir, &name);
+        match dest {
+            Some(_) if entry.size() > budget => extracted.skip(&name, "File is larger than the size limit"),
+            Some(dest) => match extract_entry(entry_reader, &dest, &mut budget) {
+                Ok(_) => extracted.files.push(dest),
+                Err(e) => extracted.skip(&name, e)
+            },
+            None => {}
+        }
+        // Entries of a solid archive share one stream, so whatever wasn't read must be skipped over before the next entry
+        std::io::copy(entry_reader, &mut std::io::sink())?;
+        Ok(true)
+    }).map_err(|e| format!("Could not read 7z: {}", e))?;
+    Ok(extracted)
+}
+
+/// Extracts every .vpk in the archive into dest_dir, reporting any other files as skipped.
+/// rar archives are not supported, as there is no rar reader that doesn't need the unrar C library
+pub fn extract_vpks(archive: &Path, dest_dir: &Path) -> Result<Extracted, String> {
+    let ext = archive.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let result = match ext.as_str() {
+        "zip" => extract_zip(archive, dest_dir, MAX_EXTRACTED_SIZE),
+        "7z" => extract_7z(archive, dest_dir, MAX_EXTRACTED_SIZE),
+        "rar" => Err("rar archives are not supported, extract it and import the .vpk instead".to_string()),
+        _ => Err(format!("{:?} is not a supported archive", archive))
+    };
+    match &result {
+        Ok(extracted) => debug!("extracted {} vpks from {:?}, skipped {}", extracted.files.len(), archive, extracted.skipped.len()),
+        Err(e) => warn!("Could not extract {:?}: {}", archive, e)
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use sevenz_rust::{SeqReader, SevenZArchiveEntry, SevenZWriter, SourceReader};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+    use super::{extract_7z, extract_zip, Extracted, MAX_EXTRACTED_SIZE};
+
+    /// Creates an empty folder to write the archive and extract into
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("extract")).unwrap();
+        dir
+    }
+
+    fn write_zip(path: &PathBuf, entries: &[(&str, &[u8], CompressionMethod)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, content, method) in entries {
+            zip.start_file(*name, SimpleFileOptions::default().compression_method(*method)).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Bytes that don't compress well
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn reasons(extracted: &Extracted) -> Vec<(&str, &str)> {
+        extracted.skipped.iter().map(|file| (file.name.as_str(), file.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn skips_paths_leaving_the_folder() {
+        let dir = test_dir("archive_zip_slip");
+        let archive = dir.join("addons.zip");
+        write_zip(&archive, &[
+            ("../escape.vpk", b"1", CompressionMethod::Stored),
+            ("/absolute.vpk", b"2", CompressionMethod::Stored),
+            ("C:/drive.vpk", b"3", CompressionMethod::Stored),
+            ("nested\\..\\..\\up.vpk", b"4", CompressionMethod::Stored),
+            ("nested/./addon.vpk", b"5", CompressionMethod::Stored),
+            ("readme.txt", b"6", CompressionMethod::Stored)
+        ]);
+        let extracted = extract_zip(&archive, &dir.join("extract"), MAX_EXTRACTED_SIZE).unwrap();
+        assert_eq!(extracted.files, [dir.join("extract").join("addon.vpk")]);
+        assert_eq!(reasons(&extracted), [
+            ("../escape.vpk", "Path leaves the archive"),
+            ("/absolute.vpk", "Path leaves the archive"),
+            ("C:/drive.vpk", "Path leaves the archive"),
+            ("nested\\..\\..\\up.vpk", "Path leaves the archive"),
+            ("readme.txt", "Not a .vpk file")
+        ]);
+        assert!(!dir.join("escape.vpk").exists());
+        assert!(!dir.join("up.vpk").exists());
+    }
+
+    #[test]
+    fn stops_at_size_limit() {
+        let dir = test_dir("archive_size_limit");
+        let archive = dir.join("addons.zip");
+        let content = noise(600);
+        write_zip(&archive, &[
+            ("first.vpk", &content, CompressionMethod::Stored),
+            ("second.vpk", &content, CompressionMethod::Stored),
+            ("small.vpk", &content[..100], CompressionMethod::Stored)
+        ]);
+        let extracted = extract_zip(&archive, &dir.join("extract"), 1000).unwrap();
+        assert_eq!(extracted.files, [dir.join("extract").join("first.vpk"), dir.join("extract").join("small.vpk")]);
+        assert_eq!(reasons(&extracted), [("second.vpk", "File is larger than the size limit")]);
+        assert!(!dir.join("extract").join("second.vpk").exists());
+    }
+
+    #[test]
+    fn skips_suspicious_compression_ratio() {
+        let dir = test_dir("archive_ratio");
+        let archive = dir.join("addons.zip");
+        let zeros = vec![0u8; 1024 * 1024];
+        let content = noise(64 * 1024);
+        write_zip(&archive, &[
+            ("bomb.vpk", &zeros, CompressionMethod::Deflated),
+            ("addon.vpk", &content, CompressionMethod::Deflated)
+        ]);
+        let extracted = extract_zip(&archive, &dir.join("extract"), MAX_EXTRACTED_SIZE).unwrap();
+        assert_eq!(extracted.files, [dir.join("extract").join("addon.vpk")]);
+        assert_eq!(reasons(&extracted), [("bomb.vpk", "File is compressed suspiciously well")]);
+        assert_eq!(std::fs::read(dir.join("extract").join("addon.vpk")).unwrap(), content);
+    }
+
+    #[test]
+    fn reads_vpk_after_skipped_entries_in_solid_7z() {
+        let dir = test_dir("archive_solid_7z");
+        let archive = dir.join("addons.7z");
+        let readme = noise(300);
+        let big = noise(2000);
+        let addon = noise(700)[500..].to_vec();
+        let entries = ["readme.txt", "big.vpk", "addon.vpk"].iter().map(|name| {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            entry
+        }).collect();
+        let readers = SeqReader::new([&readme, &big, &addon].iter()
+            .map(|content| SourceReader::new(content.as_slice()))
+            .collect());
+        let mut writer = SevenZWriter::create(&archive).unwrap();
+        writer.push_archive_entries(entries, readers).unwrap();
+        writer.finish().unwrap();
+
+        let extracted = extract_7z(&archive, &dir.join("extract"), 1000).unwrap();
+        assert_eq!(extracted.files, [dir.join("extract").join("addon.vpk")]);
+        assert_eq!(reasons(&extracted), [
+            ("readme.txt", "Not a .vpk file"),
+            ("big.vpk", "File is larger than the size limit")
+        ]);
+        assert_eq!(std::fs::read(dir.join("extract").join("addon.vpk")).unwrap(), addon);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use crate::addonlist::{self, ToggleMode};
+use crate::install::{self, MigrateOriginal};
+use crate::{trash, util};
+
+/// How many addons are worked on at once
+const BULK_CONCURRENCY: usize = 4;
+
+/// An addon to work on, by its path or the workshop id in its file name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum AddonTarget {
+    WorkshopId(u32),
+    Path(PathBuf)
+}
+
+impl std::fmt::Display for AddonTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddonTarget::WorkshopId(id) => write!(f, "{}", id),
+            AddonTarget::Path(path) => write!(f, "{}", path.display())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BulkAction {
+    Toggle(ToggleMode),
+    Delete,
+    /// Unsubscribe is treated as Remove, as opening hundreds of workshop pages is not useful
+    Migrate(MigrateOriginal),
+    Move(PathBuf)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BulkResult {
+    pub target: AddonTarget,
+    /// Path the target resolved to, None if no addon was found
+    pub path: Option<PathBuf>,
+    /// Path after the change, which is inside the trash for deletes. None if it failed
+    pub new_path: Option<PathBuf>,
+    /// Id of the addon's trash entry, for deletes that succeeded
+    pub trash_id: Option<String>,
+    pub error: Option<String>,
+    /// Change succeeded, but was undone because another addon failed
+    pub rolled_back: bool
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BulkReport {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Every change was undone, as all_or_nothing was set and an addon failed
+    pub rolled_back: bool,
+    pub items: Vec<BulkResult>
+}
+
+/// Sent on the "bulk-progress" event after each addon is done
+#[derive(Serialize, Clone)]
+pub struct BulkProgressPayload {
+    pub done: usize,
+    pub total: usize,
+    pub target: AddonTarget,
+    pub error: Option<String>
+}
+
+/// Finds the path of every workshop id in the addons and workshop folder
+fn get_workshop_paths(addons_dir: &Path) -> Result<HashMap<u32, PathBuf>, String> {
+    let mut paths = HashMap::new();
+    for folder in [addons_dir.to_path_buf(), addons_dir.join("workshop")] {
+        if !folder.exists() { continue; }
+        for entry in util::get_vpks_in_folder(&folder)? {
+            let path = entry.path();
+            if let Some(id) = util::find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy()) {
+                paths.entry(id).or_insert(path);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Runs the action on a single addon, recording its new path. When it may be rolled back, migrations keep the original until commit_action
+fn run_action(action: &BulkAction, item: &mut BulkResult, staged: bool) -> Result<(), String> {
+    let path = item.path.as_ref().ok_or_else(|| "No addon found with that workshop id".to_string())?;
+    if !path.is_file() {
+        return Err(format!("File does not exist at {:?}", path));
+    }
+    let new_path = match action {
+        BulkAction::Toggle(mode) => addonlist::toggle(path, *mode)?,
+        BulkAction::Delete => {
+            let entry = trash::trash(path)?;
+            item.trash_id = Some(entry.id.clone());
+            entry.get_path()
+        },
+        BulkAction::Migrate(_) if staged => install::migrate(path, MigrateOriginal::Keep)?,
+        BulkAction::Migrate(original) => install::migrate(path, *original)?,
+        BulkAction::Move(dest_dir) => install::move_addon(path, dest_dir)?
+    };
+    item.new_path = Some(new_path);
+    Ok(())
+}
+
+/// Undoes a change made by run_action with staged set
+fn undo_action(action: &BulkAction, path: &Path, new_path: &Path, trash_id: Option<&str>) -> Result<(), String> {
+    match action {
+        BulkAction::Toggle(mode) => addonlist::toggle(new_path, *mode).map(|_| ()),
+        BulkAction::Delete => {
+            let id = trash_id.ok_or_else(|| "Addon has no trash entry".to_string())?;
+            trash::restore(id).map(|_| ())
+        },
+        BulkAction::Migrate(_) => install::undo_migrate(new_path),
+        BulkAction::Move(_) => install::move_addon(new_path, path.parent().unwrap()).map(|_| ())
+    }
+}
+
+/// Finishes a staged change once every addon has succeeded
+fn commit_action(action: &BulkAction, path: &Path) -> Result<(), String> {
+    match action {
+        BulkAction::Migrate(original) if *original != MigrateOriginal::Keep => install::remove_migrated_original(path),
+        _ => Ok(())
+    }
+}
+
+/// Runs the action on every target, a few at a time, calling on_progress as each one finishes.
+/// With all_or_nothing, no more addons are started after one fails, and every finished change is undone
+pub fn run(addons_dir: &Path, targets: Vec<AddonTarget>, action: BulkAction, all_or_nothing: bool, on_progress: impl Fn(BulkProgressPayload) + Sync) -> Result<BulkReport, String> {
+    let workshop_paths = match targets.iter().any(|t| matches!(t, AddonTarget::WorkshopId(_))) {
+        true => get_workshop_paths(addons_dir)?,
+        false => HashMap::new()
+    };
+    let total = targets.len();
+    let items: Vec<Mutex<BulkResult>> = targets.into_iter().map(|target| {
+        let path = match &target {
+            AddonTarget::WorkshopId(id) => workshop_paths.get(id).cloned(),
+            AddonTarget::Path(path) => Some(path.clone())
+        };
+        Mutex::new(BulkResult { target, path, new_path: None, trash_id: None, error: None, rolled_back: false })
+    }).collect();
+    debug!("bulk {:?} on {} addons, all_or_nothing={}", action, total, all_or_nothing);
+
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for _ in 0..BULK_CONCURRENCY.min(total) {
+            scope.spawn(|| loop {
+                if all_or_nothing && failed.load(Ordering::Relaxed) { break; }
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break; };
+                let mut item = item.lock().unwrap();
+                if let Err(e) = run_action(&action, &mut item, all_or_nothing) {
+                    warn!("bulk {:?} failed for {}: {}", action, item.target, e);
+                    failed.store(true, Ordering::Relaxed);
+                    item.error = Some(e);
+                }
+                on_progress(BulkProgressPayload {
+                    done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                    target: item.target.clone(),
+                    error: item.error.clone()
+                });
+            });
+        }
+    });
+
+    let mut items: Vec<BulkResult> = items.into_iter().map(|item| item.into_inner().unwrap()).collect();
+    let failed = failed.into_inner();
+    if all_or_nothing {
+        for item in items.iter_mut().rev() {
+            let (Some(path), Some(new_path)) = (&item.path, &item.new_path) else {
+                if failed && item.error.is_none() {
+                    item.error = Some("Not started, as another addon failed".to_string());
+                }
+                continue;
+            };
+            let result = match failed {
+                true => undo_action(&action, path, new_path, item.trash_id.as_deref()),
+                false => commit_action(&action, path)
+            };
+            match result {
+                Err(e) => {
+                    error!("Could not {} {:?} for {}: {}", if failed { "undo" } else { "finish" }, action, item.target, e);
+                    item.error = Some(e);
+                },
+                Ok(_) if failed => {
+                    item.rolled_back = true;
+                    item.new_path = None;
+                    item.trash_id = None;
+                },
+                Ok(_) => {}
+            }
+        }
+    }
+    let failed_count = items.iter().filter(|item| item.error.is_some()).count();
+    Ok(BulkReport {
+        succeeded: items.iter().filter(|item| item.error.is_none() && !item.rolled_back).count(),
+        failed: failed_count,
+        rolled_back: all_or_nothing && failed,
+        items
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use regex::Regex;
+    use crate::addonlist::ToggleMode;
+    use crate::util::WORKSHOP_ID_REGEX;
+    use super::{run, AddonTarget, BulkAction, BULK_CONCURRENCY};
+
+    /// Creates an addons folder holding the vpks
+    fn addons_dir(name: &str, files: &[&str]) -> PathBuf {
+        // Set up by main
+        WORKSHOP_ID_REGEX.get_or_init(|| Regex::new(r"[0-9]{4,}").unwrap());
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name)
+            .join("addons");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), file.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn runs_every_target() {
+        let names: Vec<String> = (1..=10).map(|i| format!("{}.vpk", 1000 + i)).collect();
+        let dir = addons_dir("bulk_every", &names.iter().map(|n| n.as_str()).collect::<Vec<_>>());
+        let mut targets: Vec<AddonTarget> = names[..5].iter().map(|name| AddonTarget::Path(dir.join(name))).collect();
+        targets.extend((6..=10).map(|i| AddonTarget::WorkshopId(1000 + i)));
+
+        let progress = Mutex::new(vec![]);
+        let threads = Mutex::new(HashSet::new());
+        let report = run(&dir, targets, BulkAction::Toggle(ToggleMode::Rename), false, |payload| {
+            threads.lock().unwrap().insert(std::thread::current().id());
+            progress.lock().unwrap().push((payload.done, payload.total));
+        }).unwrap();
+
+        assert_eq!((report.succeeded, report.failed, report.rolled_back), (10, 0, false));
+        for name in &names {
+            assert!(dir.join(format!("{}.disabled", name)).exists(), "{} was not toggled", name);
+        }
+        for item in &report.items {
+            assert_eq!(item.new_path.as_ref().unwrap(), &PathBuf::from(format!("{}.disabled", item.path.as_ref().unwrap().display())));
+        }
+        let mut progress = progress.into_inner().unwrap();
+        progress.sort();
+        assert_eq!(progress, (1..=10).map(|done| (done, 10)).collect::<Vec<_>>());
+        assert!(threads.into_inner().unwrap().len() <= BULK_CONCURRENCY);
+    }
+
+    #[test]
+    fn reports_failures_without_all_or_nothing() {
+        let dir = addons_dir("bulk_partial", &["1.vpk", "2.vpk"]);
+        let targets = vec![
+            AddonTarget::Path(dir.join("1.vpk")),
+            AddonTarget::WorkshopId(404),
+            AddonTarget::Path(dir.join("2.vpk"))
+        ];
+        let report = run(&dir, targets, BulkAction::Toggle(ToggleMode::Rename), false, |_| {}).unwrap();
+        assert_eq!((report.succeeded, report.failed, report.rolled_back), (2, 1, false));
+        assert!(report.items[1].path.is_none());
+        assert!(report.items[1].error.is_some());
+        assert!(dir.join("1.vpk.disabled").exists());
+        assert!(dir.join("2.vpk.disabled").exists());
+    }
+
+    #[test]
+    fn all_or_nothing_rolls_back() {
+        let names: Vec<String> = (1..=8).map(|i| format!("addon_{}.vpk", i)).collect();
+        let dir = addons_dir("bulk_rollback", &names.iter().map(|n| n.as_str()).collect::<Vec<_>>());
+        let mut targets: Vec<AddonTarget> = names.iter().map(|name| AddonTarget::Path(dir.join(name))).collect();
+        targets.insert(5, AddonTarget::Path(dir.join("missing.vpk")));
+
+        let report = run(&dir, targets, BulkAction::Toggle(ToggleMode::Rename), true, |_| {}).unwrap();
+
+        assert!(report.rolled_back);
+        assert_eq!(report.succeeded, 0);
+        let failed = &report.items[5];
+        assert!(failed.error.as_ref().unwrap().contains("does not exist"));
+        assert!(!failed.rolled_back);
+        for item in report.items.iter().filter(|item| item.error.is_none()) {
+            assert!(item.rolled_back);
+            assert!(item.new_path.is_none());
+        }
+        // Addons that were not started are reported as failed
+        assert_eq!(report.failed, report.items.iter().filter(|item| !item.rolled_back).count());
+        for name in &names {
+            assert!(dir.join(name).exists(), "{} was not restored", name);
+            assert!(!dir.join(format!("{}.disabled", name)).exists());
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use crate::config::Settings;
+use crate::steam::SteamApi;
+use crate::util;
+
+/// Bumped whenever the format of the cache files changes, older files are re-fetched
+pub const CACHE_SCHEMA_VERSION: u32 = 2;
+/// Used when settings do not specify workshop_cache_ttl_hours
+pub const DEFAULT_CACHE_TTL_HOURS: u64 = 24;
+/// Folder next to the addons that holds the cached workshop info
+pub const CACHE_FOLDER_NAME: &str = ".addon_manager";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum WorkshopStatus {
+    /// Item is public on the workshop
+    Live,
+    /// Item was deleted from the workshop
+    Removed,
+    /// Item was made private or friends only
+    Private,
+    /// Steam did not return the item, for an unknown reason
+    Unknown
+}
+
+/// Contents of .addon_manager/<id>.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedWorkshopInfo {
+    pub schema_version: u32,
+    /// Unix timestamp of when the info was last fetched from steam
+    pub fetched_at: u64,
+    pub status: WorkshopStatus,
+    /// Latest info steam returned, kept after the item goes missing
+    pub item: Option<WorkshopItem>,
+    /// Unix timestamp of when steam first stopped returning the item
+    pub missing_since: Option<u64>
+}
+
+impl CachedWorkshopInfo {
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        now() >= self.fetched_at + ttl.as_secs()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub fn get_cache_path(addon_path: &Path, workshop_id: u32) -> Option<PathBuf> {
+    Some(addon_path.parent()?.join(CACHE_FOLDER_NAME).join(format!("{}.json", workshop_id)))
+}
+
+/// Reads the cache file for the addon, returning None if it's missing or from an older version
+pub fn read_cache(addon_path: &Path, workshop_id: u32) -> Option<CachedWorkshopInfo> {
+    let path = get_cache_path(addon_path, workshop_id)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let cached: CachedWorkshopInfo = serde_json::from_str(&content).ok()?;
+            if cached.schema_version != CACHE_SCHEMA_VERSION {
+                return None
+            }
+            Some(cached)
+        },
+        Err(e) => {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("Could not read cached workshop info at {:?}: {}", path, e);
+            }
+            None
+        }
+    }
+}
+
+/// Gets the cached workshop info, regardless of how old it is.
+/// For items no longer on the workshop, this is the last info that was seen
+pub fn get_cached_workshop_info(addon_path: &Path, workshop_id: u32) -> Option<WorkshopItem> {
+    read_cache(addon_path, workshop_id).and_then(|c| c.item)
+}
+
+fn write_cache(addon_path: &Path, workshop_id: u32, cached: &CachedWorkshopInfo) {
+    let content = serde_json::to_string(cached).unwrap();
+    let mut path = addon_path.parent().unwrap().join(CACHE_FOLDER_NAME);
+    if !path.exists() {
+        std::fs::create_dir(&path).ok();
+    }
+    path.push(format!("{}.json", workshop_id));
+    debug!("writing cache to {:?}", &path);
+    std::fs::write(path, content).ok();
+}
+
+/// Writes the workshop info to the .addon_manager folder next to the addon
+pub fn save_cached_workshop_info(addon_path: &Path, item: &WorkshopItem) {
+    let Ok(workshop_id) = item.publishedfileid.parse() else { return };
+    write_cache(addon_path, workshop_id, &CachedWorkshopInfo {
+        schema_version: CACHE_SCHEMA_VERSION,
+        fetched_at: now(),
+        status: WorkshopStatus::Live,
+        item: Some(item.clone()),
+        missing_since: None
+    });
+}
+
+/// Copies the addon's cache file to sit next to the addon's new path, returning false if it had none
+pub fn copy_cache(from: &Path, to: &Path, workshop_id: u32) -> bool {
+    match read_cache(from, workshop_id) {
+        Some(cached) => {
+            write_cache(to, workshop_id, &cached);
+            true
+        },
+        None => false
+    }
+}
+
+/// Records that steam no longer returns the item, keeping any previously cached info
+pub fn save_missing_workshop_info(addon_path: &Path, workshop_id: u32, status: WorkshopStatus) -> CachedWorkshopInfo {
+    let previous = read_cache(addon_path, workshop_id);
+    let now = now();
+    let cached = CachedWorkshopInfo {
+        schema_version: CACHE_SCHEMA_VERSION,
+        fetched_at: now,
+        status,
+        missing_since: previous.as_ref().and_then(|p| p.missing_since).or(Some(now)),
+        item: previous.and_then(|p| p.item)
+    };
+    write_cache(addon_path, workshop_id, &cached);
+    cached
+}
+
+/// Deletes cache files in the folder's .addon_manager that no longer have an addon, returning how many were removed
+pub fn remove_orphaned(dir: &Path) -> Result<usize, String> {
+    let cache_dir = dir.join(CACHE_FOLDER_NAME);
+    if !cache_dir.exists() {
+        return Ok(0)
+    }
+    let installed: HashSet<u32> = util::get_vpks_in_folder(dir)?.iter()
+        .filter_map(|entry| util::find_workshop_id_in_str(&entry.path().file_stem().unwrap().to_string_lossy()))
+        .collect();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&cache_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().is_none_or(|ext| ext != "json") { continue; }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        if !installed.contains(&id) {
+            debug!("removing orphaned cache entry {:?}", path);
+            if std::fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Re-fetches workshop info for every addon in the folders (or only the one with the given id), ignoring the cache.
+/// Returns how many addons had their cache refreshed
+pub fn refresh(settings: &Settings, dirs: &[&Path], only: Option<u32>) -> Result<usize, String> {
+    let mut addons: Vec<(u32, PathBuf)> = vec![];
+    for dir in dirs {
+        if !dir.exists() { continue; }
+        remove_orphaned(dir)?;
+        for entry in util::get_vpks_in_folder(dir)? {
+            let path = entry.path();
+            let Some(id) = util::find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy()) else {
+                continue;
+            };
+            if only.is_none_or(|only| only == id) {
+                addons.push((id, path));
+            }
+        }
+    }
+    let mut ids: Vec<u32> = addons.iter().map(|(id, _)| *id).collect();
+    ids.sort();
+    ids.dedup();
+    let mut refreshed = 0;
+    let details = util::fetch_workshop_details(&SteamApi::new(settings), &ids);
+    for item in &details.items {
+        for (_, path) in addons.iter().filter(|(id, _)| item.publishedfileid == id.to_string()) {
+            save_cached_workshop_info(path, item);
+            refreshed += 1;
+        }
+    }
+    for (id, path) in &addons {
+        // Ids from a failed batch have no status, keep their existing entry as there's no way to tell if they are missing
+        match details.statuses.get(id) {
+            Some(WorkshopStatus::Live) | None => continue,
+            Some(&status) => {
+                save_missing_workshop_info(path, *id, status);
+                refreshed += 1;
+            }
+        }
+    }
+    debug!("refreshed {} cache entries", refreshed);
+    Ok(refreshed)
+}
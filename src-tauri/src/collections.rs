@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use crate::config::Settings;
+use crate::downloads::DownloadManager;
+use crate::steam::SteamApi;
+use crate::util;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CollectionEntry {
+    pub publishedfileid: u32,
+    /// None if steam did not return the item's details
+    pub title: Option<String>,
+    /// Already in the addons or workshop folder
+    pub installed: bool,
+    /// Download job, if the item was queued
+    pub job_id: Option<u64>,
+    pub error: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CollectionImport {
+    pub collection_id: u32,
+    pub items: Vec<CollectionEntry>
+}
+
+/// Workshop ids of every addon in the addons and workshop folder, enabled or not
+pub fn get_installed_ids(addons_dir: &Path) -> Result<HashSet<u32>, String> {
+    let mut ids = HashSet::new();
+    for folder in [addons_dir.to_path_buf(), addons_dir.join("workshop")] {
+        if !folder.exists() { continue; }
+        for entry in util::get_vpks_in_folder(&folder)? {
+            if let Some(id) = util::find_workshop_id_in_str(&entry.path().file_stem().unwrap().to_string_lossy()) {
+                ids.insert(id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// Every item in the collection with its workshop details, None if steam did not return any
+fn get_items(api: &SteamApi, collection_id: u32) -> Result<Vec<(u32, Option<WorkshopItem>)>, String> {
+    let ids = api.get_collection_items(collection_id)?;
+    let mut details = util::fetch_workshop_items(api, &ids);
+    Ok(ids.into_iter().map(|id| {
+        let item = details.iter().position(|item| item.publishedfileid == id.to_string())
+            .map(|i| details.swap_remove(i));
+        (id, item)
+    }).collect())
+}
+
+/// Resolves the collection's items, queueing downloads for any that are not installed
+pub fn import(settings: &Settings, downloads: &Arc<Mutex<DownloadManager>>, collection_id: u32) -> Result<CollectionImport, String> {
+    let addons_dir = settings.gamedir.as_ref()
+        .ok_or_else(|| "No addons folder is set".to_string())?;
+    let collection_items = get_items(&SteamApi::new(settings), collection_id)?;
+    let installed = get_installed_ids(addons_dir)?;
+
+    let mut items = Vec::with_capacity(collection_items.len());
+    let mut downloads = downloads.lock().unwrap();
+    for (id, item) in collection_items {
+        let mut entry = CollectionEntry {
+            publishedfileid: id,
+            title: item.as_ref().map(|item| item.title.clone()),
+            installed: installed.contains(&id),
+            job_id: None,
+            error: None
+        };
+        if !entry.installed {
+            match item {
+                Some(item) => match downloads.add_item(item) {
+                    Ok(job_id) => entry.job_id = Some(job_id),
+                    Err(e) => {
+                        error!("Could not queue collection item {}: {}", id, e);
+                        entry.error = Some(e);
+                    }
+                },
+                None => entry.error = Some("No workshop info found, it may be private or deleted".to_string())
+            }
+        }
+        items.push(entry);
+    }
+    debug!("imported collection {}: {} items, {} queued", collection_id, items.len(), items.iter().filter(|i| i.job_id.is_some()).count());
+    Ok(CollectionImport { collection_id, items })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use crate::config::Settings;
+    use crate::steam::SteamApi;
+    use super::get_items;
+
+    /// Serves responses from route, given each request's path and body, for the rest of the test run
+    fn mock_steam(route: fn(&str, &str) -> String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() { break; }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let response = route(&path, &String::from_utf8_lossy(&body));
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", response.len(), response).unwrap();
+            }
+        });
+        url
+    }
+
+    fn item_json(id: u32, title: &str) -> String {
+        format!(r#"{{"result":1,"publishedfileid":"{id}","creator":"76561197960287930","creator_app_id":550,"consumer_app_id":550,
+            "filename":"","file_size":"1024","file_url":"https://example.com/{id}.vpk","hcontent_file":"1","preview_url":"https://example.com/{id}.jpg",
+            "hcontent_preview":"1","title":"{title}","description":"","time_created":1600000000,"time_updated":1600000000,"visibility":0,
+            "banned":0,"ban_reason":"","subscriptions":1,"favorited":1,"lifetime_subscriptions":1,"lifetime_favorited":1,"views":1,
+            "tags":[{{"tag":"Campaigns"}}]}}"#)
+    }
+
+    fn route(path: &str, body: &str) -> String {
+        match path {
+            "/ISteamRemoteStorage/GetCollectionDetails/v1/" if body.contains("=10") => r#"{"response":{"result":1,"resultcount":1,"collectiondetails":[
+                {"publishedfileid":"10","result":1,"children":[
+                    {"publishedfileid":"101","sortorder":1,"filetype":0},
+                    {"publishedfileid":"20","sortorder":2,"filetype":2},
+                    {"publishedfileid":"102","sortorder":3,"filetype":0}
+                ]}
+            ]}}"#.to_string(),
+            "/ISteamRemoteStorage/GetCollectionDetails/v1/" => r#"{"response":{"result":1,"resultcount":1,"collectiondetails":[
+                {"publishedfileid":"20","result":1,"children":[
+                    {"publishedfileid":"103","sortorder":1,"filetype":0},
+                    {"publishedfileid":"101","sortorder":2,"filetype":0}
+                ]}
+            ]}}"#.to_string(),
+            "/ISteamRemoteStorage/GetPublishedFileDetails/v1/" => format!(
+                r#"{{"response":{{"result":1,"resultcount":3,"publishedfiledetails":[{},{{"publishedfileid":"102","result":9}},{}]}}}}"#,
+                item_json(101, "First"), item_json(103, "Nested")
+            ),
+            _ => r#"{"response":{}}"#.to_string()
+        }
+    }
+
+    #[test]
+    fn gets_nested_collection_items() {
+        let settings = Settings { steam_api_url: Some(mock_steam(route)), ..Default::default() };
+        let items = get_items(&SteamApi::new(&settings), 10).unwrap();
+        let items: Vec<(u32, Option<String>)> = items.into_iter()
+            .map(|(id, item)| (id, item.map(|item| item.title)))
+            .collect();
+        assert_eq!(items, vec![
+            (101, Some("First".to_string())),
+            (102, None),
+            (103, Some("Nested".to_string()))
+        ]);
+    }
+}
@@ -1,36 +1,56 @@
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
-use futures::StreamExt;
 use log::{debug, error};
 use regex::Regex;
-use steam_workshop_api::{SearchOptions, WorkshopItem};
-use tauri::Window;
-use crate::{config, Data, ErrorPayload, UpdatePayload, util};
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use tauri::{AppHandle, Manager};
+use crate::{addonlist, archive, bulk, cache, collections, config, conflicts, Data, install, profiles, trash, updates, util, vpk};
+use crate::collections::CollectionImport;
+use crate::conflicts::ConflictGroup;
+use crate::addonlist::{AddonList, AddonState};
+use crate::bulk::{AddonTarget, BulkAction, BulkReport};
+use crate::downloads::{DownloadJob, DownloadManager};
+use crate::install::{ArchiveInstall, ImportSource, MigrateOriginal};
+use crate::profiles::{Profile, ProfileDiff};
+use crate::steam::{SearchPage, SearchQuery, SearchSort, SteamApi};
+use crate::trash::TrashEntry;
+use crate::updates::AddonUpdate;
 use crate::util::AddonEntry;
+use crate::vpk::VpkFile;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AddonStateMismatch {
+    file_path: PathBuf,
+    state: AddonState
+}
 
 #[allow(dead_code)]
 
 #[tauri::command]
 pub fn get_my_addons(state: tauri::State<'_, Data>) -> Result<Vec<util::AddonEntry>, String> {
-    let settings = state.settings.lock().unwrap();
-    let path = settings.get().gamedir.as_ref().unwrap().to_owned();
-    let ws = state.workshop.clone();
-    util::get_addons(&ws, &path)
+    let settings = state.settings.lock().unwrap().get_clone();
+    let path = settings.gamedir.as_ref().unwrap().to_owned();
+    util::get_addons(&settings, &path)
 }
 
 #[tauri::command]
 pub fn get_workshop_addons(state: tauri::State<'_, Data>) -> Result<Vec<util::AddonEntry>, String> {
-    let settings = state.settings.lock().unwrap();
-    let path = settings.get().gamedir.as_ref().unwrap().join("workshop");
-    let ws = &state.workshop.clone();
-    util::get_addons(&ws, &path)
+    let settings = state.settings.lock().unwrap().get_clone();
+    let path = settings.gamedir.as_ref().unwrap().join("workshop");
+    util::get_addons(&settings, &path)
+}
+
+/// Runs a function that calls steam on a blocking thread, as retries sleep between attempts
+async fn run_blocking<T: Send + 'static>(func: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    tauri::async_runtime::spawn_blocking(func).await
+        .map_err(|e| e.to_string())?
 }
 
 #[tauri::command]
-pub fn get_latest_workshop_info(state: tauri::State<'_, Data>, publishedfileid: u32) -> Result<WorkshopItem, String> {
-    let ws = &state.workshop.clone();
-    match util::get_workshop_info(ws, publishedfileid) {
+pub async fn get_latest_workshop_info(state: tauri::State<'_, Data>, publishedfileid: u32) -> Result<WorkshopItem, String> {
+    let settings = state.settings.lock().unwrap().get_clone();
+    match run_blocking(move || util::get_workshop_info(&SteamApi::new(&settings), publishedfileid)).await {
         Ok(None) => Err("Could not find workshop info, may have been deleted or made private".to_string()),
         Err(e) => Err(e),
         Ok(Some(item)) => Ok(item)
@@ -55,106 +75,84 @@ pub fn save_settings(state: tauri::State<Data>, changed: config::Settings) -> Re
     Ok(())
 }
 
+/// Results per page when search_workshop is not given a page_size
+const DEFAULT_SEARCH_PAGE_SIZE: u32 = 30;
+
 pub static WORKSHOP_URL_REGEX: OnceLock<Regex> = OnceLock::new();
-#[tauri::command]
-pub fn search_workshop(state: tauri::State<Data>, query: &str) -> Result<Vec<WorkshopItem>, String> {
-    // TODO: strip out url, and search for publishedfileid directly
-
-    let ws = &state.workshop.clone();
-    ws.search_items(&SearchOptions {
-        count: 30,
-        app_id: 550,
-        query: query.to_string(),
-        cursor: None,
-        required_tags: None,
-        excluded_tags: None,
-    })
-        .map_err(|e| e.to_string())
-        .map(|r| r.items)
+/// Gets the workshop id out of a workshop url, steam:// link, or a bare id
+pub fn parse_workshop_id(input: &str) -> Option<u32> {
+    let input = input.trim();
+    if let Some(captures) = WORKSHOP_URL_REGEX.get().unwrap().captures(input) {
+        return captures[1].parse().ok()
+    }
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return util::find_workshop_id_in_str(input)
+    }
+    None
 }
 
 #[tauri::command]
-async fn download_addon(state: tauri::State<'_, Data>, window: Window, published_file_id: u32) -> Result<(), String> {
-    let config = &state.settings;
-    let dest_file_path = config.lock().unwrap().get().gamedir.as_ref().unwrap().join(format!("{}.vpk", published_file_id));
-    let mut part_file_path = dest_file_path.clone();
-    part_file_path.set_file_name(format!("{}.vpk.part", published_file_id));
-    let mut file = {
-        std::fs::File::create(&part_file_path).expect("Could not create part file")
-    };
-    let item = util::get_workshop_info(&state.workshop.clone(), published_file_id)?;
-    let item = item.ok_or_else(|| "Could not find workshop item".to_string() )?;
-    // TODO: get workshop item
-    let mut bytes_downloaded: usize = 0;
-    let bytes_total: usize = item.file_size.parse().unwrap();
-    debug!("Starting download of id={} title={} bytes_total={}", published_file_id, item.title, bytes_total);
-    match reqwest::Client::new()
-        .get(&item.file_url.unwrap())
-        .header("User-Agent", "L4D2-Workshop-Downloader")
-        .send()
-        .await
-    {
-        Ok(response) => {
-            let mut stream = response.bytes_stream();
-            let mut chunk_index: u8 = 0;
-            while let Some(result) = stream.next().await {
-                match result {
-                    Ok(chunk) => {
-                        if let Err(err) = file.write(&chunk) {
-                            error!("[{}] Write Error: {}", published_file_id, err);
-                            break;
-                        }
-                        bytes_downloaded += chunk.len();
-                        chunk_index += 1;
-                        if chunk_index > 100 {
-                            chunk_index = 0;
-                            window.emit("progress", UpdatePayload {
-                                publishedfileid: published_file_id,
-                                bytes_downloaded,
-                                bytes_total,
-                                complete: false
-                            }).ok();
-                        }
-                    },
-                    Err(err) => {
-                        window.emit("progress", ErrorPayload {
-                            publishedfileid: published_file_id,
-                            error: err.to_string()
-                        }).ok();
-                        error!("Download for {} failed:\n{}", published_file_id, &err);
-                        return Err(err.to_string())
-                    }
-                }
-            }
-            file.flush().ok();
-            std::fs::rename(part_file_path, dest_file_path)
-                .map_err(|e| e.to_string())?;
-            window.emit("progress", UpdatePayload {
-                publishedfileid: published_file_id,
-                bytes_downloaded,
-                bytes_total,
-                complete: true
-            }).ok();
-            debug!("Downloaded (id {}) ({} bytes)", published_file_id, bytes_downloaded);
-            return Ok(())
-        },
-        Err(err) => {
-            println!("Download failure for {}: {}", published_file_id, err);
-            return Err(err.to_string())
+pub async fn search_workshop(state: tauri::State<'_, Data>, query: String, required_tags: Option<Vec<String>>, excluded_tags: Option<Vec<String>>, sort: Option<SearchSort>, page_size: Option<u32>, cursor: Option<String>) -> Result<SearchPage, String> {
+    let settings = state.settings.lock().unwrap().get_clone();
+    run_blocking(move || {
+        let api = SteamApi::new(&settings);
+        // Links and ids go straight to the item instead of searching for them
+        if let Some(publishedfileid) = parse_workshop_id(&query) {
+            debug!("search_workshop: looking up id {}", publishedfileid);
+            let items: Vec<WorkshopItem> = util::get_workshop_info(&api, publishedfileid)?
+                .into_iter().collect();
+            return Ok(SearchPage { total: items.len() as u64, items, next_cursor: None });
         }
-    }
+
+        api.search(&SearchQuery {
+            text: query,
+            required_tags: required_tags.unwrap_or_default(),
+            excluded_tags: excluded_tags.unwrap_or_default(),
+            sort: sort.unwrap_or_default(),
+            page_size: page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE),
+            cursor
+        })
+    }).await
 }
+
+/// Moves the addon to the trash, where it can be restored from until it's purged
 #[tauri::command]
-pub(crate) fn delete_addon(path: &str) -> Result<(), String> {
+pub(crate) fn delete_addon(state: tauri::State<'_, Data>, path: &str) -> Result<TrashEntry, String> {
     let path = PathBuf::from(path);
     if !path.exists() {
         return Err(format!("File does not exist at {:?}", path).to_string());
     } else if path.is_dir() {
         return Err(format!("File path {:?} provided is a folder", path).to_string());
-    } else {
-        debug!("deleting {:?}", path);
-        std::fs::remove_file(path).map_err(|e| e.to_string())
     }
+    debug!("deleting {:?}", path);
+    let entry = trash::trash(&path)?;
+    enforce_trash_retention(&state, std::slice::from_ref(&entry.id));
+    Ok(entry)
+}
+
+/// Purges the trash down to its limits, except for the addons that were just deleted
+fn enforce_trash_retention(state: &Data, deleted: &[String]) {
+    let settings = state.settings.lock().unwrap().get().clone();
+    if let Err(e) = trash::enforce_retention(&settings, deleted) {
+        error!("Could not purge old addons from trash: {}", e);
+    }
+}
+
+#[tauri::command]
+pub fn get_trash() -> Result<Vec<TrashEntry>, String> {
+    trash::list()
+}
+
+#[tauri::command]
+pub fn restore_addon(id: &str) -> Result<AddonEntry, String> {
+    let path = trash::restore(id)?;
+    util::get_addon_info(&path)
+}
+
+/// Permanently deletes an addon from the trash, or every addon if no id is given
+#[tauri::command]
+pub fn purge_trash(id: Option<String>) -> Result<usize, String> {
+    trash::purge(id.as_deref())
 }
 #[tauri::command]
 pub(crate) fn toggle_addon(state: tauri::State<'_, Data>, path: &str) -> Result<AddonEntry, String> {
@@ -162,38 +160,291 @@ pub(crate) fn toggle_addon(state: tauri::State<'_, Data>, path: &str) -> Result<
     if path.is_dir() {
         return Err(format!("File path {:?} provided is a folder", path).to_string());
     }
-    let file_name = path.file_name().unwrap().to_string_lossy();
-    let new_path;
-    if file_name.ends_with(".disabled") {
-        // Remove the .disabled:
-        new_path = path.clone().with_extension("");
-    } else if file_name.ends_with(".vpk") {
-        // Add on .disabled:
-        new_path = path.clone().with_file_name(format!("{}.disabled", file_name));
-    } else {
-        return Err("Filename does not end with .disabled or .vpk, cannot toggle".to_string());
-    }
-    debug!("toggle_addon {:?} -> {:?}", &path, &new_path);
-    std::fs::rename(&path, &new_path).map_err(|e| e.to_string())?;
+    let toggle_mode = state.settings.lock().unwrap().get().toggle_mode;
+    let new_path = addonlist::toggle(&path, toggle_mode)?;
     util::get_addon_info(&new_path)
 }
 
+async fn run_bulk(state: &Data, app: AppHandle, targets: Vec<AddonTarget>, action: BulkAction, all_or_nothing: Option<bool>) -> Result<BulkReport, String> {
+    let addons_dir = state.settings.lock().unwrap().get().gamedir.clone()
+        .ok_or_else(|| "No addons folder is set".to_string())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        bulk::run(&addons_dir, targets, action, all_or_nothing.unwrap_or(false), |progress| {
+            app.emit_all("bulk-progress", progress).ok();
+        })
+    })
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub async fn toggle_addons(state: tauri::State<'_, Data>, app: AppHandle, targets: Vec<AddonTarget>, all_or_nothing: Option<bool>) -> Result<BulkReport, String> {
+    let toggle_mode = state.settings.lock().unwrap().get().toggle_mode;
+    run_bulk(&state, app, targets, BulkAction::Toggle(toggle_mode), all_or_nothing).await
+}
+
+#[tauri::command]
+pub async fn delete_addons(state: tauri::State<'_, Data>, app: AppHandle, targets: Vec<AddonTarget>, all_or_nothing: Option<bool>) -> Result<BulkReport, String> {
+    let report = run_bulk(&state, app, targets, BulkAction::Delete, all_or_nothing).await?;
+    let deleted: Vec<String> = report.items.iter()
+        .filter_map(|item| item.trash_id.clone())
+        .collect();
+    enforce_trash_retention(&state, &deleted);
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn migrate_addons(state: tauri::State<'_, Data>, app: AppHandle, targets: Vec<AddonTarget>, original: Option<MigrateOriginal>, all_or_nothing: Option<bool>) -> Result<BulkReport, String> {
+    run_bulk(&state, app, targets, BulkAction::Migrate(original.unwrap_or_default()), all_or_nothing).await
+}
+
+/// Moves the addons into another folder
+#[tauri::command]
+pub async fn move_addons(state: tauri::State<'_, Data>, app: AppHandle, targets: Vec<AddonTarget>, dest: PathBuf, all_or_nothing: Option<bool>) -> Result<BulkReport, String> {
+    run_bulk(&state, app, targets, BulkAction::Move(dest), all_or_nothing).await
+}
+
+/// Lists every file inside the addon's vpk
+#[tauri::command]
+pub fn get_vpk_files(path: &str) -> Result<Vec<VpkFile>, String> {
+    vpk::list_files(Path::new(path))
+}
+
+/// Extracts a file, or every file matching a glob, out of the addon's vpk into dest
+#[tauri::command]
+pub fn extract_vpk_files(path: &str, pattern: &str, dest: &str) -> Result<Vec<PathBuf>, String> {
+    vpk::extract(Path::new(path), pattern, Path::new(dest))
+}
+
+/// Finds files that more than one enabled addon replaces, and which addon the game will use
+#[tauri::command]
+pub fn get_addon_conflicts(state: tauri::State<'_, Data>) -> Result<Vec<ConflictGroup>, String> {
+    let addons_dir = state.settings.lock().unwrap().get().gamedir.clone()
+        .ok_or_else(|| "No addons folder is set".to_string())?;
+    conflicts::get_conflicts(&addons_dir)
+}
+
+/// Finds addons where the file name and addonlist.txt disagree on if the addon is enabled
 #[tauri::command]
-pub(crate) fn migrate_addon(state: tauri::State<'_, Data>, path: &str) -> Result<AddonEntry, String> {
+pub fn get_addon_state_mismatches(state: tauri::State<'_, Data>) -> Result<Vec<AddonStateMismatch>, String> {
+    let dir = state.settings.lock().unwrap().get().gamedir.as_ref().unwrap().to_owned();
+    let list = AddonList::load(&dir)?;
+    let mut mismatches = vec![];
+    for folder in [dir.clone(), dir.join("workshop")] {
+        if !folder.exists() { continue; }
+        for entry in util::get_vpks_in_folder(&folder)? {
+            let path = entry.path();
+            let addon_state = addonlist::get_addon_state(Some(&list), &path);
+            if addon_state.mismatch {
+                mismatches.push(AddonStateMismatch { file_path: path, state: addon_state });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Moves a workshop addon into the addons folder, so it's no longer managed by steam
+#[tauri::command]
+pub(crate) fn migrate_addon(app: AppHandle, path: &str, original: Option<MigrateOriginal>) -> Result<AddonEntry, String> {
     let path = PathBuf::from(path);
     if path.is_dir() {
         return Err(format!("File path {:?} provided is a folder", path).to_string());
     }
-    let parent_name = path.parent().unwrap().file_name().unwrap().to_string_lossy();
-    if parent_name != "workshop" {
-        return Err(format!("migrate_addon called on file that is not in a workshop folder.").to_string());
+    let original = original.unwrap_or_default();
+    let new_path = install::migrate(&path, original)?;
+    if original == MigrateOriginal::Unsubscribe {
+        if let Some(id) = util::find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy()) {
+            let url = format!("https://steamcommunity.com/sharedfiles/filedetails/?id={}", id);
+            if let Err(e) = tauri::api::shell::open(&app.shell_scope(), url, None) {
+                error!("Could not open workshop page for {}: {}", id, e);
+            }
+        }
     }
-    let file_name = path.file_name().unwrap().to_str().unwrap();
-    let new_path = path.parent().unwrap().with_file_name(file_name);
-    debug!("migrate_addon {:?} -> {:?}", &path, &new_path);
-
-    std::fs::copy(&path, &new_path).map_err(|e| e.to_string())?;
     util::get_addon_info(&new_path)
 }
 
+/// Installs an addon into the addons folder from the workshop or a .vpk file, after checking it's a valid addon
+#[tauri::command]
+pub async fn import_addon(state: tauri::State<'_, Data>, app: AppHandle, item: ImportSource) -> Result<AddonEntry, String> {
+    let addons_dir = state.settings.lock().unwrap().get().gamedir.clone()
+        .ok_or_else(|| "No addons folder is set".to_string())?;
+    debug!("import_addon {:?}", item);
+    let publishedfileid = match item {
+        ImportSource::Path(path) if archive::is_archive(&path) => {
+            let mut install = install::install_archive(&path, &addons_dir)?;
+            if install.installed.is_empty() {
+                let reasons: Vec<String> = install.skipped.iter().map(|s| format!("{}: {}", s.name, s.reason)).collect();
+                return Err(format!("No valid addons found in archive:\n{}", reasons.join("\n")))
+            }
+            // Use install_archive to see every addon that was installed
+            return Ok(install.installed.remove(0))
+        },
+        ImportSource::Path(path) => {
+            let dest = install::install_file(&path, &addons_dir)?;
+            return util::get_addon_info(&dest)
+        },
+        workshop => workshop.workshop_id()?
+    };
+
+    // Steam may have already downloaded it to the workshop folder, move it out so the game doesn't load it twice
+    let workshop_path = addons_dir.join("workshop").join(format!("{}.vpk", publishedfileid));
+    if workshop_path.exists() {
+        util::get_addon_data(&workshop_path)
+            .map_err(|e| format!("Workshop item {} is not a valid addon: {}", publishedfileid, e))?;
+        let dest = install::migrate(&workshop_path, MigrateOriginal::Remove)?;
+        return util::get_addon_info(&dest)
+    }
+    install::check_not_installed(&addons_dir, &format!("{}.vpk", publishedfileid))?;
+    let settings = state.settings.lock().unwrap().get_clone();
+    let item = run_blocking(move || get_download_item(&settings, publishedfileid)).await?;
+    let job_id = state.downloads.lock().unwrap().add_item(item)?;
+    DownloadManager::process(&state.downloads, &app);
+    let dest = DownloadManager::wait_for(&state.downloads, job_id).await?;
+    // Downloads are checked to be vpks, but not that they're addons
+    if let Err(e) = util::get_addon_data(&dest) {
+        error!("Downloaded addon {} is not valid, removing: {}", publishedfileid, e);
+        std::fs::remove_file(&dest).ok();
+        return Err(format!("Workshop item {} is not a valid addon: {}", publishedfileid, e))
+    }
+    util::get_addon_info(&dest)
+}
+
+/// Installs every valid addon in a .zip or .7z, reporting the files that were skipped
+#[tauri::command]
+pub fn install_archive(state: tauri::State<'_, Data>, path: &str) -> Result<ArchiveInstall, String> {
+    let addons_dir = state.settings.lock().unwrap().get().gamedir.clone()
+        .ok_or_else(|| "No addons folder is set".to_string())?;
+    install::install_archive(Path::new(path), &addons_dir)
+}
+
+/// Looks up the item to download. Called before locking the download manager, as steam may take a while to respond
+fn get_download_item(settings: &config::Settings, publishedfileid: u32) -> Result<WorkshopItem, String> {
+    util::get_workshop_info(&SteamApi::new(settings), publishedfileid)?
+        .ok_or_else(|| "No workshop id found with that id. May be private, or deleted".to_string())
+}
+
+/// Queues the workshop item for download, returning the download's job id
+#[tauri::command]
+pub async fn download_addon(state: tauri::State<'_, Data>, app: AppHandle, publishedfileid: u32) -> Result<u64, String> {
+    let settings = state.settings.lock().unwrap().get_clone();
+    let item = run_blocking(move || get_download_item(&settings, publishedfileid)).await?;
+    let id = state.downloads.lock().unwrap().add_item(item)?;
+    DownloadManager::process(&state.downloads, &app);
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn get_download_queue(state: tauri::State<'_, Data>) -> Vec<DownloadJob> {
+    state.downloads.lock().unwrap().list()
+}
+
+#[tauri::command]
+pub fn cancel_download(state: tauri::State<'_, Data>, id: u64) -> Result<(), String> {
+    state.downloads.lock().unwrap().cancel(id)
+}
+
+#[tauri::command]
+pub fn pause_download(state: tauri::State<'_, Data>, id: u64) -> Result<(), String> {
+    state.downloads.lock().unwrap().pause(id)
+}
+
+#[tauri::command]
+pub fn resume_download(state: tauri::State<'_, Data>, app: AppHandle, id: u64) -> Result<(), String> {
+    state.downloads.lock().unwrap().resume(id)?;
+    DownloadManager::process(&state.downloads, &app);
+    Ok(())
+}
+
+/// Checks both the addons and workshop folder for addons with a newer workshop version
+#[tauri::command]
+pub async fn check_updates(state: tauri::State<'_, Data>) -> Result<Vec<AddonUpdate>, String> {
+    let (dir, api) = {
+        let settings = state.settings.lock().unwrap();
+        (settings.get().gamedir.as_ref().unwrap().to_owned(), SteamApi::new(settings.get()))
+    };
+    run_blocking(move || {
+        let workshop_dir = dir.join("workshop");
+        updates::check_updates(&api, &[&dir, &workshop_dir])
+    }).await
+}
+
+/// Queues outdated addons to be downloaded over their existing file.
+/// If publishedfileids is set, only those addons are updated. Returns the queued job ids
+#[tauri::command]
+pub async fn update_addons(state: tauri::State<'_, Data>, app: AppHandle, publishedfileids: Option<Vec<u32>>) -> Result<Vec<u64>, String> {
+    let updates = check_updates(state.clone()).await?;
+    let mut job_ids = vec![];
+    {
+        let mut downloads = state.downloads.lock().unwrap();
+        for update in updates {
+            if let Some(ids) = &publishedfileids {
+                if !ids.contains(&update.publishedfileid) { continue; }
+            }
+            if update.is_steam_managed() {
+                debug!("skipping update for {}, steam updates the workshop folder", update.publishedfileid);
+                continue;
+            }
+            debug!("queueing update for {} ({:?})", update.publishedfileid, update.file_path);
+            job_ids.push(downloads.add_item_to(update.workshop_info, update.file_path)?);
+        }
+    }
+    DownloadManager::process(&state.downloads, &app);
+    Ok(job_ids)
+}
+
+/// Fetches the workshop info again for the addon with the given id, or every addon if not set.
+/// Returns how many cache entries were refreshed
+#[tauri::command]
+pub async fn refresh_workshop_cache(state: tauri::State<'_, Data>, publishedfileid: Option<u32>) -> Result<usize, String> {
+    let settings = state.settings.lock().unwrap().get_clone();
+    run_blocking(move || {
+        let dir = settings.gamedir.as_ref().unwrap().to_owned();
+        let workshop_dir = dir.join("workshop");
+        cache::refresh(&settings, &[&dir, &workshop_dir], publishedfileid)
+    }).await
+}
+
+#[tauri::command]
+pub fn get_profiles() -> Result<Vec<Profile>, String> {
+    profiles::list()
+}
+
+/// Saves the currently enabled addons as a new profile
+#[tauri::command]
+pub fn create_profile(state: tauri::State<'_, Data>, name: &str) -> Result<Profile, String> {
+    let dir = state.settings.lock().unwrap().get().gamedir.as_ref().unwrap().to_owned();
+    profiles::create(name, &dir)
+}
+
+/// Shows what applying the profile would change, without changing anything
+#[tauri::command]
+pub fn diff_profile(state: tauri::State<'_, Data>, name: &str) -> Result<ProfileDiff, String> {
+    let dir = state.settings.lock().unwrap().get().gamedir.as_ref().unwrap().to_owned();
+    profiles::diff(&profiles::load(name)?, &dir)
+}
+
+#[tauri::command]
+pub fn apply_profile(state: tauri::State<'_, Data>, name: &str) -> Result<ProfileDiff, String> {
+    let (dir, toggle_mode) = {
+        let settings = state.settings.lock().unwrap();
+        (settings.get().gamedir.as_ref().unwrap().to_owned(), settings.get().toggle_mode)
+    };
+    profiles::apply(&profiles::load(name)?, &dir, toggle_mode)
+}
+
+#[tauri::command]
+pub fn delete_profile(name: &str) -> Result<(), String> {
+    profiles::delete(name)
+}
 
+/// Queues every item of a workshop collection that isn't already installed. Accepts a collection url or id
+#[tauri::command]
+pub async fn import_collection(state: tauri::State<'_, Data>, app: AppHandle, collection: String) -> Result<CollectionImport, String> {
+    let collection_id = parse_workshop_id(&collection)
+        .ok_or_else(|| format!("\"{}\" is not a workshop collection url or id", collection))?;
+    let settings = state.settings.lock().unwrap().get_clone();
+    let downloads = state.downloads.clone();
+    let result = run_blocking(move || collections::import(&settings, &downloads, collection_id)).await?;
+    DownloadManager::process(&state.downloads, &app);
+    Ok(result)
+}
@@ -1,19 +1,41 @@
 use std::{path::PathBuf, fs};
+use std::time::Duration;
 use log::debug;
 use serde::{Deserialize, Serialize};
+use crate::addonlist::ToggleMode;
+use crate::cache::DEFAULT_CACHE_TTL_HOURS;
+use crate::retry::RetryPolicy;
+use crate::trash::{DEFAULT_TRASH_MAX_AGE_DAYS, DEFAULT_TRASH_MAX_SIZE_MB};
 
 #[cfg(debug_assertions)]
 const APPDATA_FOLDER_NAME: &str = "l4d2-workshop-dev";
 #[cfg(not(debug_assertions))]
 const APPDATA_FOLDER_NAME: &str = "l4d2-workshop-downloader";
 
+#[cfg(test)]
+thread_local! {
+    /// Used instead of the config folder by tests, so they don't touch real app data
+    static TEST_APPDIR: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Makes get_appdir return the folder for the rest of the test's thread
+#[cfg(test)]
+pub fn set_test_appdir(folder: PathBuf) {
+    TEST_APPDIR.with(|dir| *dir.borrow_mut() = Some(folder));
+}
+
 pub fn get_appdir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(folder) = TEST_APPDIR.with(|dir| dir.borrow().clone()) {
+        fs::create_dir_all(&folder).expect("Could not create test config folder");
+        return folder;
+    }
 
     let folder = dirs::config_dir().expect("Could not find a valid config folder").join(APPDATA_FOLDER_NAME);
     if !folder.exists() {
         fs::create_dir_all(&folder).expect("Could not create config folder");
     }
-    return folder;
+    folder
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -21,7 +43,48 @@ pub struct Settings {
     pub gamedir: Option<PathBuf>,
     pub version: Option<String>,
     pub steam_apikey: Option<String>,
-    pub telemetry: bool
+    pub telemetry: bool,
+    #[serde(default)]
+    pub max_concurrent_downloads: Option<u8>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// How often to check for addon updates in the background, disabled if not set
+    #[serde(default)]
+    pub update_check_interval_minutes: Option<u64>,
+    /// Queue downloads for any updates found by the background check
+    #[serde(default)]
+    pub auto_update: bool,
+    /// Unix timestamp of the last background update check
+    #[serde(default)]
+    pub last_update_check: Option<u64>,
+    /// How long cached workshop info is used before it is fetched again
+    #[serde(default)]
+    pub workshop_cache_ttl_hours: Option<u64>,
+    #[serde(default)]
+    pub toggle_mode: ToggleMode,
+    /// Overrides the Steam Web API url, for pointing at a local mock
+    #[serde(default)]
+    pub steam_api_url: Option<String>,
+    /// Oldest deleted addons are purged from the trash once it's larger than this
+    #[serde(default)]
+    pub trash_max_size_mb: Option<u64>,
+    /// Deleted addons are purged from the trash after this many days
+    #[serde(default)]
+    pub trash_max_age_days: Option<u64>
+}
+
+impl Settings {
+    pub fn workshop_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.workshop_cache_ttl_hours.unwrap_or(DEFAULT_CACHE_TTL_HOURS) * 60 * 60)
+    }
+
+    pub fn trash_max_size(&self) -> u64 {
+        self.trash_max_size_mb.unwrap_or(DEFAULT_TRASH_MAX_SIZE_MB) * 1024 * 1024
+    }
+
+    pub fn trash_max_age(&self) -> Duration {
+        Duration::from_secs(self.trash_max_age_days.unwrap_or(DEFAULT_TRASH_MAX_AGE_DAYS) * 24 * 60 * 60)
+    }
 }
 pub struct SettingsManager {
     config_path: PathBuf,
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::addonlist;
+use crate::config::get_appdir;
+use crate::{util, vpk};
+
+/// Files every addon has, which describe the addon rather than replace anything in game
+const IGNORED_FILES: [&str; 3] = ["addoninfo.txt", "addonimage.jpg", "addonimage.vtf"];
+
+/// A vpk's file list, reused until the vpk is modified
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedFileList {
+    modified: u64,
+    files: Vec<String>
+}
+
+/// Addons that all contain the same files
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConflictGroup {
+    /// In load order, the first one is what the game uses
+    pub addons: Vec<PathBuf>,
+    pub winner: PathBuf,
+    pub files: Vec<String>
+}
+
+fn get_cache_path() -> PathBuf {
+    get_appdir().join("vpk_files.json")
+}
+
+fn read_cache() -> HashMap<PathBuf, CachedFileList> {
+    std::fs::read_to_string(get_cache_path()).ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache(cache: &HashMap<PathBuf, CachedFileList>) {
+    match serde_json::to_string(cache) {
+        Ok(content) => if let Err(e) = std::fs::write(get_cache_path(), content) {
+            warn!("Could not save vpk file cache: {}", e);
+        },
+        Err(e) => warn!("Could not save vpk file cache: {}", e)
+    }
+}
+
+/// Enabled addons in the order the game loads them: the addons folder then the workshop folder,
+/// each sorted by file name. When two addons have the same file, the one loaded first is used
+fn get_enabled_in_load_order(addons_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let list = addonlist::load_for_folder(addons_dir);
+    let mut addons = vec![];
+    for folder in [addons_dir.to_path_buf(), addons_dir.join("workshop")] {
+        if !folder.exists() { continue; }
+        let mut paths: Vec<PathBuf> = util::get_vpks_in_folder(&folder)?.iter()
+            .map(|entry| entry.path())
+            .filter(|path| addonlist::get_addon_state(list.as_ref(), path).enabled)
+            .collect();
+        paths.sort_by_key(|path| path.file_name().unwrap().to_string_lossy().to_lowercase());
+        addons.extend(paths);
+    }
+    Ok(addons)
+}
+
+/// Finds files that more than one enabled addon replaces, grouped by the addons that share them
+pub fn get_conflicts(addons_dir: &Path) -> Result<Vec<ConflictGroup>, String> {
+    let addons = get_enabled_in_load_order(addons_dir)?;
+    let mut cache = read_cache();
+    let mut new_cache = HashMap::with_capacity(addons.len());
+    // File path -> indexes of the addons that have it, in load order
+    let mut owners: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, path) in addons.iter().enumerate() {
+        let modified = path.metadata().ok()
+            .and_then(|meta| meta.modified().ok())
+            .map_or(0, |time| time.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        let files = match cache.remove(path) {
+            Some(cached) if cached.modified == modified => cached,
+            _ => match vpk::get_file_paths(path) {
+                Ok(files) => CachedFileList { modified, files },
+                Err(e) => {
+                    warn!("Could not read files of {:?}, skipping: {}", path, e);
+                    continue;
+                }
+            }
+        };
+        for file in &files.files {
+            if IGNORED_FILES.contains(&file.as_str()) { continue; }
+            owners.entry(file.clone()).or_default().push(i);
+        }
+        new_cache.insert(path.clone(), files);
+    }
+    // Only addons that are still enabled are kept in the cache
+    write_cache(&new_cache);
+
+    let mut groups: BTreeMap<Vec<usize>, Vec<String>> = BTreeMap::new();
+    for (file, owners) in owners {
+        if owners.len() > 1 {
+            groups.entry(owners).or_default().push(file);
+        }
+    }
+    debug!("found {} conflict groups across {} addons", groups.len(), addons.len());
+    Ok(groups.into_iter().map(|(owners, mut files)| {
+        files.sort();
+        ConflictGroup {
+            winner: addons[owners[0]].clone(),
+            addons: owners.iter().map(|i| addons[*i].clone()).collect(),
+            files
+        }
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+    use crate::config::set_test_appdir;
+    use crate::vpk::write_test_vpk;
+    use super::{get_cache_path, get_conflicts, read_cache, write_cache};
+
+    /// Creates a game folder with an empty addons and workshop folder, using its own app folder
+    fn setup(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        set_test_appdir(dir.join("appdir"));
+        let addons_dir = dir.join("left4dead2").join("addons");
+        std::fs::create_dir_all(addons_dir.join("workshop")).unwrap();
+        addons_dir
+    }
+
+    fn add(path: &Path, files: &[&str]) {
+        let files: Vec<(&str, &[u8])> = files.iter().map(|file| (*file, file.as_bytes())).collect();
+        write_test_vpk(path, &files);
+    }
+
+    #[test]
+    fn addons_folder_loads_before_workshop() {
+        let addons_dir = setup("conflicts_load_order");
+        add(&addons_dir.join("workshop").join("111.vpk"), &["materials/hud.vmt", "addoninfo.txt"]);
+        add(&addons_dir.join("workshop").join("222.vpk"), &["materials/hud.vmt", "sound/music.wav"]);
+        add(&addons_dir.join("zz_hud.vpk"), &["materials/hud.vmt", "addoninfo.txt"]);
+        add(&addons_dir.join("Aa_hud.vpk"), &["materials/hud.vmt", "sound/music.wav"]);
+        add(&addons_dir.join("disabled.vpk.disabled"), &["materials/hud.vmt"]);
+
+        let groups = get_conflicts(&addons_dir).unwrap();
+        assert_eq!(groups.len(), 2);
+        let hud = groups.iter().find(|group| group.files == ["materials/hud.vmt"]).unwrap();
+        assert_eq!(hud.addons, [
+            addons_dir.join("Aa_hud.vpk"),
+            addons_dir.join("zz_hud.vpk"),
+            addons_dir.join("workshop").join("111.vpk"),
+            addons_dir.join("workshop").join("222.vpk")
+        ]);
+        assert_eq!(hud.winner, addons_dir.join("Aa_hud.vpk"));
+        // addoninfo.txt is in every addon, so it's not a conflict
+        let music = groups.iter().find(|group| group.files == ["sound/music.wav"]).unwrap();
+        assert_eq!(music.addons, [addons_dir.join("Aa_hud.vpk"), addons_dir.join("workshop").join("222.vpk")]);
+        assert_eq!(music.winner, addons_dir.join("Aa_hud.vpk"));
+    }
+
+    #[test]
+    fn cache_is_invalidated_when_vpk_changes() {
+        let addons_dir = setup("conflicts_cache");
+        let first = addons_dir.join("first.vpk");
+        let second = addons_dir.join("second.vpk");
+        add(&first, &["models/a.mdl"]);
+        add(&second, &["models/a.mdl"]);
+        assert_eq!(get_conflicts(&addons_dir).unwrap().len(), 1);
+        assert!(get_cache_path().exists());
+
+        // An unchanged vpk is read from the cache
+        let mut cache = read_cache();
+        assert_eq!(cache.len(), 2);
+        cache.get_mut(&second).unwrap().files = vec!["models/b.mdl".to_string()];
+        write_cache(&cache);
+        assert!(get_conflicts(&addons_dir).unwrap().is_empty());
+
+        // Once it is modified, its files are read again
+        add(&second, &["models/a.mdl", "models/c.mdl"]);
+        let modified = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options().write(true).open(&second).unwrap().set_modified(modified).unwrap();
+        let groups = get_conflicts(&addons_dir).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files, ["models/a.mdl"]);
+        assert_eq!(read_cache()[&second].files.len(), 2);
+
+        // Addons that are no longer enabled are dropped from the cache
+        std::fs::rename(&first, addons_dir.join("first.vpk.disabled")).unwrap();
+        assert!(get_conflicts(&addons_dir).unwrap().is_empty());
+        assert!(!read_cache().contains_key(&first));
+    }
+}
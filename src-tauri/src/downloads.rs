@@ -1,50 +1,651 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use steam_workshop_api::{SteamWorkshop, WorkshopItem};
-use crate::config::SettingsManager;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use reqwest::{header, Response, StatusCode};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use tauri::{AppHandle, Manager};
+use sourcepak::pak::v1::format::VPKVersion1;
+use crate::config::{get_appdir, SettingsManager};
+use crate::retry::{self, RetryPolicy};
+use crate::{cache, util};
+use crate::{ErrorPayload, UpdatePayload};
+
+/// How often "progress" events are sent for a running download
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often wait_for checks if a download has finished
+const WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Used when settings do not specify max_concurrent_downloads
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: u8 = 2;
+
+// Signals sent to a running download task
+const SIGNAL_RUN: u8 = 0;
+const SIGNAL_PAUSE: u8 = 1;
+const SIGNAL_CANCEL: u8 = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Paused,
+    Complete,
+    Failed,
+    Cancelled
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", content = "details")]
+pub enum DownloadError {
+    /// Network error
+    Request(String),
+    /// Server responded with an error status
+    Status(u16),
+    /// Server responded with 429 Too Many Requests
+    RateLimited { retry_after_secs: Option<u64> },
+    /// Workshop item cannot be downloaded
+    Unavailable(String),
+    /// Could not write the downloaded file
+    Io(String),
+    /// Stream ended before the full file was received
+    SizeMismatch { expected: u64, actual: u64 },
+    /// Downloaded file is not a readable vpk
+    InvalidVpk(String),
+    /// Download was paused or cancelled
+    Stopped
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Request(e) => write!(f, "Request failed: {}", e),
+            DownloadError::Status(code) => write!(f, "Server responded with status {}", code),
+            DownloadError::RateLimited { .. } => write!(f, "Rate limited by server"),
+            DownloadError::Unavailable(e) => write!(f, "Item is not available for download: {}", e),
+            DownloadError::Io(e) => write!(f, "Could not write file: {}", e),
+            DownloadError::SizeMismatch { expected, actual } => write!(f, "Downloaded {} bytes, expected {} bytes", actual, expected),
+            DownloadError::InvalidVpk(e) => write!(f, "Downloaded file is not a valid vpk: {}", e),
+            DownloadError::Stopped => write!(f, "Download stopped")
+        }
+    }
+}
+
+impl DownloadError {
+    /// Can the download succeed if tried again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Request(_) | DownloadError::RateLimited { .. } => true,
+            DownloadError::Status(code) => *code >= 500,
+            _ => false
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            DownloadError::RateLimited { retry_after_secs } => retry_after_secs.map(Duration::from_secs),
+            _ => None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub publishedfileid: u32,
+    pub item: WorkshopItem,
+    /// Where the finished vpk will be placed
+    pub dest: PathBuf,
+    pub state: DownloadState,
+    pub bytes_downloaded: u64,
+    pub error: Option<DownloadError>
+}
+
+impl DownloadJob {
+    pub fn part_path(&self) -> PathBuf {
+        let mut path = self.dest.clone().into_os_string();
+        path.push(".part");
+        PathBuf::from(path)
+    }
+
+    /// Size reported by steam, 0 if unknown
+    pub fn bytes_total(&self) -> u64 {
+        self.item.file_size.parse().unwrap_or(0)
+    }
+
+    /// Is the job still waiting on, or running, a download
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state, DownloadState::Queued | DownloadState::Downloading | DownloadState::Paused)
+    }
+}
 
 pub struct DownloadManager {
-    ws: SteamWorkshop,
-    downloads: Vec<WorkshopItem>,
-    config: Arc<Mutex<SettingsManager>>
+    downloads: Vec<DownloadJob>,
+    config: Arc<Mutex<SettingsManager>>,
+    queue_path: PathBuf,
+    next_id: u64,
+    /// Signal flags of the currently running downloads, by job id
+    active: HashMap<u64, Arc<AtomicU8>>
 }
 
 impl DownloadManager {
-    pub fn new(ws: SteamWorkshop, config: Arc<Mutex<SettingsManager>>) -> Self {
-        Self {
+    pub fn new(config: Arc<Mutex<SettingsManager>>) -> Self {
+        let mut manager = Self {
             downloads: vec![],
-            ws,
-            config
+            config,
+            queue_path: get_appdir().join("downloads.json"),
+            next_id: 1,
+            active: HashMap::new()
+        };
+        if let Err(e) = manager.load() {
+            error!("Could not load download queue from {:?}: {}", manager.queue_path, e);
+        }
+        manager
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.queue_path.exists() {
+            return Ok(())
+        }
+        let content = std::fs::read_to_string(&self.queue_path)
+            .map_err(|e| e.to_string())?;
+        let mut jobs: Vec<DownloadJob> = serde_json::from_str(&content)
+            .map_err(|e| e.to_string())?;
+        for job in jobs.iter_mut() {
+            // Anything that was running when the app closed has to be started again
+            if job.state == DownloadState::Downloading {
+                job.state = DownloadState::Queued;
+            }
         }
+        self.next_id = jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        debug!("loaded {} downloads from queue", jobs.len());
+        self.downloads = jobs;
+        Ok(())
+    }
+
+    /// Saves all unfinished downloads, so they can be resumed on next start
+    pub fn save(&self) -> Result<(), String> {
+        let jobs: Vec<&DownloadJob> = self.downloads.iter()
+            .filter(|j| j.is_pending() || j.state == DownloadState::Failed)
+            .collect();
+        let content = serde_json::to_string(&jobs)
+            .map_err(|e| e.to_string())?;
+        std::fs::write(&self.queue_path, content)
+            .map_err(|e| e.to_string())
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.config.lock().unwrap().get().retry.clone()
+    }
+
+    fn max_concurrent(&self) -> usize {
+        self.config.lock().unwrap().get().max_concurrent_downloads
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_DOWNLOADS)
+            .max(1) as usize
     }
 
     pub fn count(&self) -> usize {
         self.downloads.len()
     }
 
-    pub fn add_item(&mut self, item: WorkshopItem) -> usize {
-        self.add_item(item)
+    pub fn list(&self) -> Vec<DownloadJob> {
+        self.downloads.clone()
     }
 
-    pub fn add_item_by_id(&mut self, id: u32) -> Result<usize, String> {
-        // TODO: resolve
-        let mut items = self.ws.get_published_file_details(&[id.to_string()])
-            .map_err(|e| e.to_string())?;
-        if items.len() == 0 {
-            return Err("No workshop id found with that id. May be private, or deleted".to_string());
+    pub fn get(&self, id: u64) -> Option<&DownloadJob> {
+        self.downloads.iter().find(|j| j.id == id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut DownloadJob> {
+        self.downloads.iter_mut().find(|j| j.id == id)
+    }
+
+    /// Queues the item to be downloaded into the addons folder, returning the job id.
+    /// If the item is already queued, the existing job id is returned
+    pub fn add_item(&mut self, item: WorkshopItem) -> Result<u64, String> {
+        let dest = self.config.lock().unwrap().get().gamedir.as_ref()
+            .ok_or_else(|| "No addons folder is set".to_string())?
+            .join(format!("{}.vpk", item.publishedfileid));
+        self.add_item_to(item, dest)
+    }
+
+    /// Queues the item to be downloaded to dest, replacing any file already there
+    pub fn add_item_to(&mut self, item: WorkshopItem, dest: PathBuf) -> Result<u64, String> {
+        let publishedfileid: u32 = item.publishedfileid.parse()
+            .map_err(|_| format!("Invalid publishedfileid \"{}\"", item.publishedfileid))?;
+        if let Some(job) = self.downloads.iter().find(|j| j.publishedfileid == publishedfileid && j.dest == dest && j.is_pending()) {
+            return Ok(job.id)
         }
-        let item = items.remove(0);
-        Ok(self.add_item(item))
+        let id = self.next_id;
+        self.next_id += 1;
+        debug!("queued download id={} publishedfileid={} title={}", id, publishedfileid, item.title);
+        self.downloads.push(DownloadJob {
+            id,
+            publishedfileid,
+            item,
+            dest,
+            state: DownloadState::Queued,
+            bytes_downloaded: 0,
+            error: None
+        });
+        self.save().ok();
+        Ok(id)
     }
 
+    /// Removes all finished downloads from the list
     pub fn clear(&mut self) {
-        self.downloads.clear()
+        self.downloads.retain(|j| j.is_pending() || j.state == DownloadState::Failed);
+        self.save().ok();
     }
 
-    pub fn process(&mut self) {
-        if self.downloads.len() == 0 { return; }
-        let item = self.downloads.pop().unwrap();
-        let dest_file_path = self.config.lock().unwrap().get().gamedir.as_ref().unwrap().join(format!("{}.vpk", item.publishedfileid));
-        let mut part_file_path = dest_file_path.clone();
-        
+    pub fn cancel(&mut self, id: u64) -> Result<(), String> {
+        if let Some(signal) = self.active.get(&id) {
+            // Task will clean up after itself once it sees the signal
+            signal.store(SIGNAL_CANCEL, Ordering::Relaxed);
+            return Ok(())
+        }
+        let job = self.get_mut(id).ok_or_else(|| format!("No download with id {}", id))?;
+        if !job.is_pending() && job.state != DownloadState::Failed {
+            return Err("Download has already finished".to_string());
+        }
+        job.state = DownloadState::Cancelled;
+        let part_path = job.part_path();
+        if part_path.exists() {
+            std::fs::remove_file(&part_path).ok();
+        }
+        self.save()
     }
-}
\ No newline at end of file
+
+    pub fn pause(&mut self, id: u64) -> Result<(), String> {
+        if let Some(signal) = self.active.get(&id) {
+            signal.store(SIGNAL_PAUSE, Ordering::Relaxed);
+            return Ok(())
+        }
+        let job = self.get_mut(id).ok_or_else(|| format!("No download with id {}", id))?;
+        if job.state != DownloadState::Queued {
+            return Err("Only queued downloads can be paused".to_string());
+        }
+        job.state = DownloadState::Paused;
+        self.save()
+    }
+
+    /// Puts a paused or failed download back into the queue
+    pub fn resume(&mut self, id: u64) -> Result<(), String> {
+        let job = self.get_mut(id).ok_or_else(|| format!("No download with id {}", id))?;
+        match job.state {
+            DownloadState::Paused | DownloadState::Failed => {
+                job.state = DownloadState::Queued;
+                job.error = None;
+                self.save()
+            },
+            _ => Err("Only paused or failed downloads can be resumed".to_string())
+        }
+    }
+
+    /// Starts queued downloads until the concurrency limit is reached
+    pub fn process(manager: &Arc<Mutex<DownloadManager>>, app: &AppHandle) {
+        let mut dm = manager.lock().unwrap();
+        let max = dm.max_concurrent();
+        while dm.active.len() < max {
+            let Some(job) = dm.downloads.iter_mut().find(|j| j.state == DownloadState::Queued) else {
+                break;
+            };
+            job.state = DownloadState::Downloading;
+            let job = job.clone();
+            let signal = Arc::new(AtomicU8::new(SIGNAL_RUN));
+            dm.active.insert(job.id, signal.clone());
+
+            let manager = manager.clone();
+            let app = app.clone();
+            let policy = dm.retry_policy();
+            tauri::async_runtime::spawn(async move {
+                let mut attempt = 1;
+                let result = loop {
+                    match run_download(&manager, &app, &job, &signal).await {
+                        Err(e) if e.is_retryable() && attempt < policy.max_attempts && signal.load(Ordering::Relaxed) == SIGNAL_RUN => {
+                            let delay = policy.delay(attempt, e.retry_after());
+                            warn!("[{}] download failed (attempt {}/{}), retrying in {:?}: {}", job.publishedfileid, attempt, policy.max_attempts, delay, e);
+                            tokio::time::sleep(delay).await;
+                            attempt += 1;
+                        },
+                        result => break result
+                    }
+                };
+                DownloadManager::finish(&manager, &app, job.id, result, signal.load(Ordering::Relaxed));
+            });
+        }
+        dm.save().ok();
+    }
+
+    /// Waits for the download to finish, returning the path it was saved to. Fails if the download is paused
+    pub async fn wait_for(manager: &Arc<Mutex<DownloadManager>>, id: u64) -> Result<PathBuf, String> {
+        loop {
+            {
+                let dm = manager.lock().unwrap();
+                let job = dm.get(id).ok_or_else(|| format!("No download with id {}", id))?;
+                match job.state {
+                    // Addon may have been disabled while it downloaded
+                    DownloadState::Complete if !job.dest.exists() => return util::get_toggled_path(&job.dest),
+                    DownloadState::Complete => return Ok(job.dest.clone()),
+                    DownloadState::Failed => return Err(job.error.as_ref()
+                        .map_or("Download failed".to_string(), |e| e.to_string())),
+                    DownloadState::Cancelled => return Err("Download was cancelled".to_string()),
+                    // Nothing will resume it while the caller waits, so don't wait forever
+                    DownloadState::Paused => return Err("Download was paused".to_string()),
+                    _ => {}
+                }
+            }
+            tokio::time::sleep(WAIT_INTERVAL).await;
+        }
+    }
+
+    fn finish(manager: &Arc<Mutex<DownloadManager>>, app: &AppHandle, id: u64, result: Result<u64, DownloadError>, signal: u8) {
+        {
+            let mut dm = manager.lock().unwrap();
+            dm.active.remove(&id);
+            if let Some(job) = dm.get_mut(id) {
+                match (result, signal) {
+                    (_, SIGNAL_CANCEL) => {
+                        debug!("download {} cancelled", id);
+                        job.state = DownloadState::Cancelled;
+                        std::fs::remove_file(job.part_path()).ok();
+                    },
+                    (_, SIGNAL_PAUSE) => {
+                        debug!("download {} paused", id);
+                        job.state = DownloadState::Paused;
+                    },
+                    (Ok(bytes), _) => {
+                        job.state = DownloadState::Complete;
+                        job.bytes_downloaded = bytes;
+                    },
+                    (Err(e), _) => {
+                        error!("Download for {} failed:\n{}", job.publishedfileid, &e);
+                        emit_error(app, job, &e);
+                        job.state = DownloadState::Failed;
+                        job.error = Some(e);
+                    }
+                }
+                emit_progress(app, job, 0);
+            }
+            dm.save().ok();
+        }
+        DownloadManager::process(manager, app);
+    }
+}
+
+/// Emits the job's current state on the "progress" event
+fn emit_progress(app: &AppHandle, job: &DownloadJob, bytes_per_second: u64) {
+    let bytes_total = job.bytes_total();
+    let eta_seconds = if bytes_total > 0 && bytes_per_second > 0 {
+        Some(bytes_total.saturating_sub(job.bytes_downloaded) / bytes_per_second)
+    } else {
+        None
+    };
+    app.emit_all("progress", UpdatePayload {
+        job_id: job.id,
+        publishedfileid: job.publishedfileid,
+        state: job.state,
+        bytes_downloaded: job.bytes_downloaded,
+        bytes_total,
+        bytes_per_second,
+        eta_seconds
+    }).ok();
+}
+
+fn emit_error(app: &AppHandle, job: &DownloadJob, error: &DownloadError) {
+    app.emit_all("download-error", ErrorPayload {
+        job_id: job.id,
+        publishedfileid: job.publishedfileid,
+        error: error.clone()
+    }).ok();
+}
+
+/// Checks a finished part file before it is allowed to replace the live vpk.
+/// Steam does not publish a checksum for workshop files (hcontent_file is a content id, not a hash),
+/// so the size and vpk structure are all that can be checked.
+fn verify_download(job: &DownloadJob, part_path: &Path) -> Result<(), DownloadError> {
+    let actual = std::fs::metadata(part_path)
+        .map_err(|e| DownloadError::Io(e.to_string()))?
+        .len();
+    let expected = job.bytes_total();
+    if expected > 0 && actual != expected {
+        return Err(DownloadError::SizeMismatch { expected, actual });
+    }
+    let mut file = std::fs::File::open(part_path)
+        .map_err(|e| DownloadError::Io(e.to_string()))?;
+    VPKVersion1::try_from(&mut file)
+        .map_err(|e| DownloadError::InvalidVpk(e.to_string()))?;
+    Ok(())
+}
+
+async fn send_download_request(client: &reqwest::Client, url: &str, resume_from: u64) -> Result<Response, DownloadError> {
+    let mut request = client.get(url)
+        .header("User-Agent", "L4D2-Workshop-Downloader");
+    if resume_from > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", resume_from));
+    }
+    request.send().await.map_err(|e| DownloadError::Request(e.to_string()))
+}
+
+fn check_status(response: Response) -> Result<Response, DownloadError> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after_secs = retry::get_retry_after(response.headers());
+        return Err(DownloadError::RateLimited { retry_after_secs });
+    } else if status.is_client_error() || status.is_server_error() {
+        return Err(DownloadError::Status(status.as_u16()));
+    }
+    Ok(response)
+}
+
+/// Checks the response is a partial response starting where the part file left off
+fn is_resumed_response(response: &Response, resume_from: u64) -> bool {
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return false
+    }
+    // Content-Range: bytes <start>-<end>/<total>
+    response.headers().get(header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split('-').next())
+        .and_then(|start| start.trim().parse::<u64>().ok())
+        == Some(resume_from)
+}
+
+/// Downloads the url to the part file, continuing from the end of an existing part file if the server supports it.
+/// on_chunk is called with the bytes in the part file so far, before the first chunk and after each one,
+/// returning false stops the download. Returns the size of the finished part file
+async fn download_part(client: &reqwest::Client, url: &str, part_file_path: &Path, bytes_total: u64, mut on_chunk: impl FnMut(u64) -> bool) -> Result<u64, DownloadError> {
+    // Continue on from any previous attempt, as long as it's not bigger than the file should be
+    let mut resume_from = std::fs::metadata(part_file_path).map(|m| m.len()).unwrap_or(0);
+    if bytes_total > 0 && resume_from >= bytes_total {
+        resume_from = 0;
+    }
+    let mut response = send_download_request(client, url, resume_from).await?;
+    if resume_from > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        warn!("server rejected range of part file {:?}, restarting", part_file_path);
+        resume_from = 0;
+        response = send_download_request(client, url, 0).await?;
+    }
+    let response = check_status(response)?;
+    if resume_from > 0 && !is_resumed_response(&response, resume_from) {
+        // Server ignored the range and is sending the whole file
+        warn!("server does not support resuming {:?}, restarting download", part_file_path);
+        resume_from = 0;
+    }
+
+    let mut file = if resume_from > 0 {
+        debug!("resuming download of {:?} at {} bytes", part_file_path, resume_from);
+        std::fs::OpenOptions::new().append(true).open(part_file_path)
+    } else {
+        std::fs::File::create(part_file_path)
+    }.map_err(|e| DownloadError::Io(format!("Could not open part file: {}", e)))?;
+    let mut bytes_downloaded = resume_from;
+    if !on_chunk(bytes_downloaded) {
+        return Err(DownloadError::Stopped);
+    }
+    let mut stream = response.bytes_stream();
+    while let Some(result) = stream.next().await {
+        let chunk = result.map_err(|e| DownloadError::Request(e.to_string()))?;
+        if let Err(err) = file.write_all(&chunk) {
+            error!("Write error for {:?}: {}", part_file_path, err);
+            return Err(DownloadError::Io(err.to_string()));
+        }
+        bytes_downloaded += chunk.len() as u64;
+        if !on_chunk(bytes_downloaded) {
+            return Err(DownloadError::Stopped);
+        }
+    }
+    file.flush().map_err(|e| DownloadError::Io(e.to_string()))?;
+    Ok(bytes_downloaded)
+}
+
+/// Downloads the job's item to its part file, then moves it into place. Returns the amount of bytes downloaded
+async fn run_download(manager: &Arc<Mutex<DownloadManager>>, app: &AppHandle, job: &DownloadJob, signal: &AtomicU8) -> Result<u64, DownloadError> {
+    let mut job = job.clone();
+    let file_url = job.item.file_url.clone()
+        .ok_or_else(|| DownloadError::Unavailable("Workshop item has no download url".to_string()))?;
+    let part_file_path = job.part_path();
+    let client = reqwest::Client::new();
+
+    debug!("Starting download of id={} title={} bytes_total={}", job.publishedfileid, job.item.title, job.bytes_total());
+    let mut last_emit: Option<Instant> = None;
+    let mut last_bytes: u64 = 0;
+    let mut bytes_per_second: u64 = 0;
+    let bytes_total = job.bytes_total();
+    let bytes_downloaded = download_part(&client, &file_url, &part_file_path, bytes_total, |bytes| {
+        if signal.load(Ordering::Relaxed) != SIGNAL_RUN {
+            return false
+        }
+        job.bytes_downloaded = bytes;
+        match last_emit {
+            // First call is the size of the part file being resumed
+            None => {
+                last_bytes = bytes;
+                last_emit = Some(Instant::now());
+                emit_progress(app, &job, 0);
+            },
+            Some(last) if last.elapsed() >= PROGRESS_INTERVAL => {
+                let elapsed = last.elapsed();
+                let current_speed = ((bytes - last_bytes) as f64 / elapsed.as_secs_f64()) as u64;
+                // Smooth out the speed so the ETA doesn't jump around
+                bytes_per_second = if bytes_per_second == 0 { current_speed } else { (bytes_per_second * 3 + current_speed) / 4 };
+                last_bytes = bytes;
+                last_emit = Some(Instant::now());
+                if let Some(j) = manager.lock().unwrap().get_mut(job.id) {
+                    j.bytes_downloaded = bytes;
+                }
+                emit_progress(app, &job, bytes_per_second);
+            },
+            Some(_) => {}
+        }
+        true
+    }).await?;
+    job.bytes_downloaded = bytes_downloaded;
+
+    // Only replace the existing vpk once we know the new one is complete
+    if let Err(e) = verify_download(&job, &part_file_path) {
+        error!("[{}] verification failed: {}", job.publishedfileid, e);
+        // Resuming would just produce the same file, so start over next time
+        std::fs::remove_file(&part_file_path).ok();
+        return Err(e);
+    }
+    if let Some(parent) = job.dest.parent() {
+        if !parent.exists() {
+            warn!("download destination {:?} missing, creating", parent);
+            std::fs::create_dir_all(parent).ok();
+        }
+    }
+    // The addon may have been toggled while downloading, keep whatever state it is in now
+    let mut dest = job.dest.clone();
+    if !dest.exists() {
+        if let Ok(toggled) = util::get_toggled_path(&dest) {
+            if toggled.exists() {
+                dest = toggled;
+            }
+        }
+    }
+    // Rename replaces the old file in one step, so the addon is never missing
+    std::fs::rename(&part_file_path, &dest)
+        .map_err(|e| DownloadError::Io(e.to_string()))?;
+    cache::save_cached_workshop_info(&dest, &job.item);
+    debug!("Downloaded (id {}) ({} bytes) to {:?}", job.publishedfileid, job.bytes_downloaded, dest);
+    Ok(job.bytes_downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread::JoinHandle;
+    use super::download_part;
+
+    /// Serves a single request with the given status line, headers and body, returning the request's headers
+    fn serve_once(status: &str, headers: &[String], body: &'static [u8]) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/addon.vpk", listener.local_addr().unwrap());
+        let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() { break; }
+                request.push(line.trim().to_lowercase());
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    fn seed_part_file(name: &str, content: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("l4d2-addon-manager-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.vpk.part", name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn resumes_on_partial_content() {
+        let part_path = seed_part_file("resume", b"hello ");
+        let (url, server) = serve_once("206 Partial Content", &["Content-Range: bytes 6-10/11".to_string()], b"world");
+        let client = reqwest::Client::new();
+        let result = tauri::async_runtime::block_on(download_part(&client, &url, &part_path, 11, |_| true));
+
+        let request = server.join().unwrap();
+        assert!(request.contains(&"range: bytes=6-".to_string()));
+        assert_eq!(result.unwrap(), 11);
+        assert_eq!(std::fs::read(&part_path).unwrap(), b"hello world");
+        std::fs::remove_file(&part_path).ok();
+    }
+
+    #[test]
+    fn restarts_when_range_is_ignored() {
+        let part_path = seed_part_file("restart", b"stale ");
+        let (url, server) = serve_once("200 OK", &[], b"hello world");
+        let client = reqwest::Client::new();
+        let result = tauri::async_runtime::block_on(download_part(&client, &url, &part_path, 11, |_| true));
+
+        let request = server.join().unwrap();
+        assert!(request.contains(&"range: bytes=6-".to_string()));
+        assert_eq!(result.unwrap(), 11);
+        assert_eq!(std::fs::read(&part_path).unwrap(), b"hello world");
+        std::fs::remove_file(&part_path).ok();
+    }
+}
@@ -0,0 +1,289 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::addonlist;
+use crate::archive::{self, SkippedFile};
+use crate::cache;
+use crate::config::get_appdir;
+use crate::util::{self, AddonEntry};
+
+/// Where import_addon gets the addon from
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ImportSource {
+    WorkshopId(u32),
+    /// A workshop item, only its id is used
+    Workshop { publishedfileid: String },
+    /// A .vpk file or an archive of them anywhere on disk
+    Path(PathBuf)
+}
+
+impl ImportSource {
+    pub fn workshop_id(&self) -> Result<u32, String> {
+        match self {
+            ImportSource::WorkshopId(id) => Ok(*id),
+            ImportSource::Workshop { publishedfileid } => publishedfileid.parse()
+                .map_err(|_| format!("Invalid publishedfileid \"{}\"", publishedfileid)),
+            ImportSource::Path(path) => Err(format!("{:?} is not a workshop item", path))
+        }
+    }
+}
+
+/// Returns the error if an addon with the file name is already installed, enabled or not
+pub fn check_not_installed(addons_dir: &Path, file_name: &str) -> Result<PathBuf, String> {
+    let dest = addons_dir.join(file_name);
+    if dest.exists() || util::get_toggled_path(&dest).is_ok_and(|p| p.exists()) {
+        return Err(format!("An addon named {} is already installed", file_name));
+    }
+    Ok(dest)
+}
+
+/// Copies a valid addon into the addons folder, returning its new path
+pub fn install_file(src: &Path, addons_dir: &Path) -> Result<PathBuf, String> {
+    if !src.is_file() {
+        return Err(format!("File does not exist at {:?}", src));
+    }
+    if src.extension().is_none_or(|ext| !ext.eq_ignore_ascii_case("vpk")) {
+        return Err(format!("{:?} is not a .vpk file", src));
+    }
+    util::get_addon_data(src)
+        .map_err(|e| format!("{:?} is not a valid addon: {}", src, e))?;
+
+    let file_name = src.file_name().unwrap().to_string_lossy();
+    let dest = check_not_installed(addons_dir, &file_name)?;
+    // Copy next to the destination first, so the game never sees a half written addon
+    let part_path = dest.with_file_name(format!("{}.part", file_name));
+    debug!("installing {:?} -> {:?}", src, dest);
+    std::fs::copy(src, &part_path)
+        .and_then(|_| std::fs::rename(&part_path, &dest))
+        .map_err(|e| {
+            std::fs::remove_file(&part_path).ok();
+            format!("Could not copy {:?}: {}", src, e)
+        })?;
+    Ok(dest)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ArchiveInstall {
+    pub installed: Vec<AddonEntry>,
+    pub skipped: Vec<SkippedFile>
+}
+
+/// Installs every valid addon in a .zip or .7z archive
+pub fn install_archive(archive_path: &Path, addons_dir: &Path) -> Result<ArchiveInstall, String> {
+    if !archive_path.is_file() {
+        return Err(format!("File does not exist at {:?}", archive_path));
+    }
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let extract_dir = get_appdir().join("extract").join(nanos.to_string());
+    std::fs::create_dir_all(&extract_dir).map_err(|e| e.to_string())?;
+
+    let result = archive::extract_vpks(archive_path, &extract_dir).map(|extracted| {
+        let mut install = ArchiveInstall { installed: vec![], skipped: extracted.skipped };
+        for file in extracted.files {
+            let name = file.file_name().unwrap().to_string_lossy().to_string();
+            match install_file(&file, addons_dir).and_then(|dest| util::get_addon_info(&dest)) {
+                Ok(entry) => install.installed.push(entry),
+                Err(e) => install.skipped.push(SkippedFile { name, reason: e })
+            }
+        }
+        install
+    });
+    if let Err(e) = std::fs::remove_dir_all(&extract_dir) {
+        warn!("Could not remove extracted files at {:?}: {}", extract_dir, e);
+    }
+    result
+}
+
+/// What happens to the workshop copy of an addon after it's migrated
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum MigrateOriginal {
+    /// Delete it, steam will download it again unless the item is unsubscribed from
+    #[default]
+    Remove,
+    /// Leave it in the workshop folder, the game will load both copies
+    Keep,
+    /// Delete it and open the item's workshop page to unsubscribe, as steam has no api to do it for the user
+    Unsubscribe
+}
+
+/// Moves an addon out of the workshop folder into the addons folder, along with its cached workshop info
+/// and addonlist.txt state, returning its new path
+pub fn migrate(path: &Path, original: MigrateOriginal) -> Result<PathBuf, String> {
+    let workshop_dir = path.parent()
+        .filter(|parent| parent.file_name().is_some_and(|name| name == "workshop"))
+        .ok_or_else(|| format!("{:?} is not in a workshop folder", path))?;
+    let addons_dir = workshop_dir.parent().unwrap();
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let dest = check_not_installed(addons_dir, &file_name)?;
+    let size = path.metadata().map_err(|e| e.to_string())?.len();
+
+    // Copy then verify, instead of renaming, so the original is untouched if anything goes wrong
+    debug!("migrating {:?} -> {:?}", path, dest);
+    copy_verified(path, &dest, size)?;
+
+    let workshop_id = path.file_stem().and_then(|stem| util::find_workshop_id_in_str(&stem.to_string_lossy()));
+    if let Some(id) = workshop_id {
+        cache::copy_cache(path, &dest, id);
+    }
+    // Keep it enabled or disabled, as the game stores its state under the old path
+    let list_result = addonlist::update(addons_dir, |list| {
+        let old_state = addonlist::get_list_key(path).and_then(|key| list.get(&key));
+        if let Some((enabled, key)) = old_state.zip(addonlist::get_list_key(&dest)) {
+            list.set(&key, enabled);
+        }
+        Ok(())
+    });
+    if let Err(e) = list_result {
+        warn!("Could not update addonlist.txt after migrating {:?}: {}", path, e);
+    }
+
+    if original != MigrateOriginal::Keep {
+        remove_migrated_original(path)?;
+    }
+    Ok(dest)
+}
+
+/// Copies the file next to dest then renames it into place, if exactly size bytes were copied
+fn copy_verified(path: &Path, dest: &Path, size: u64) -> Result<(), String> {
+    let part_path = dest.with_file_name(format!("{}.part", dest.file_name().unwrap().to_string_lossy()));
+    let copied = std::fs::copy(path, &part_path)
+        .map_err(|e| format!("Could not copy {:?}: {}", path, e))
+        .and_then(|copied| match copied == size {
+            true => Ok(()),
+            false => Err(format!("Copied {} of {} bytes", copied, size))
+        })
+        .and_then(|_| std::fs::rename(&part_path, dest).map_err(|e| e.to_string()));
+    if copied.is_err() {
+        std::fs::remove_file(&part_path).ok();
+    }
+    copied
+}
+
+/// Removes the workshop copy of a migrated addon, along with its cached workshop info
+pub fn remove_migrated_original(path: &Path) -> Result<(), String> {
+    std::fs::remove_file(path)
+        .map_err(|e| format!("Addon was copied, but the original could not be removed: {}", e))?;
+    let workshop_id = path.file_stem().and_then(|stem| util::find_workshop_id_in_str(&stem.to_string_lossy()));
+    if let Some(cache_path) = workshop_id.and_then(|id| cache::get_cache_path(path, id)) {
+        std::fs::remove_file(cache_path).ok();
+    }
+    Ok(())
+}
+
+/// Undoes a migrate that kept the original, removing the copy along with its cached workshop info and addonlist.txt entry
+pub fn undo_migrate(dest: &Path) -> Result<(), String> {
+    std::fs::remove_file(dest).map_err(|e| format!("Could not remove {:?}: {}", dest, e))?;
+    let workshop_id = dest.file_stem().and_then(|stem| util::find_workshop_id_in_str(&stem.to_string_lossy()));
+    if let Some(cache_path) = workshop_id.and_then(|id| cache::get_cache_path(dest, id)) {
+        std::fs::remove_file(cache_path).ok();
+    }
+    let addons_dir = dest.parent().ok_or_else(|| "Addon has no parent folder".to_string())?;
+    addonlist::update(addons_dir, |list| {
+        if let Some(key) = addonlist::get_list_key(dest) {
+            list.remove(&key);
+        }
+        Ok(())
+    })
+}
+
+/// Moves an addon, and its cached workshop info, into another folder, returning its new path
+pub fn move_addon(path: &Path, dest_dir: &Path) -> Result<PathBuf, String> {
+    if !path.is_file() {
+        return Err(format!("File does not exist at {:?}", path));
+    }
+    if !dest_dir.is_dir() {
+        return Err(format!("Folder does not exist at {:?}", dest_dir));
+    }
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let dest = check_not_installed(dest_dir, &file_name)?;
+    debug!("moving {:?} -> {:?}", path, dest);
+    if std::fs::rename(path, &dest).is_err() {
+        // Renames fail across drives, fall back to copying
+        let part_path = dest.with_file_name(format!("{}.part", file_name));
+        std::fs::copy(path, &part_path)
+            .and_then(|_| std::fs::rename(&part_path, &dest))
+            .map_err(|e| {
+                std::fs::remove_file(&part_path).ok();
+                format!("Could not move {:?}: {}", path, e)
+            })?;
+        std::fs::remove_file(path)
+            .map_err(|e| format!("Addon was copied, but the original could not be removed: {}", e))?;
+    }
+    if let Some(id) = path.file_stem().and_then(|stem| util::find_workshop_id_in_str(&stem.to_string_lossy())) {
+        if cache::copy_cache(path, &dest, id) {
+            if let Some(cache_path) = cache::get_cache_path(path, id) {
+                std::fs::remove_file(cache_path).ok();
+            }
+        }
+    }
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use regex::Regex;
+    use crate::addonlist::AddonList;
+    use crate::cache::{self, WorkshopStatus};
+    use crate::util::WORKSHOP_ID_REGEX;
+    use super::{copy_verified, migrate, undo_migrate, MigrateOriginal};
+
+    /// Creates an empty addons and workshop folder
+    fn addons_dir(name: &str) -> PathBuf {
+        // Set up by main
+        WORKSHOP_ID_REGEX.get_or_init(|| Regex::new(r"[0-9]{4,}").unwrap());
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name)
+            .join("addons");
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("workshop")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn size_mismatch_leaves_nothing_behind() {
+        let dir = addons_dir("install_size_mismatch");
+        let src = dir.join("workshop").join("1234567.vpk");
+        std::fs::write(&src, [1u8; 64]).unwrap();
+        let dest = dir.join("1234567.vpk");
+
+        let err = copy_verified(&src, &dest, 65).unwrap_err();
+        assert_eq!(err, "Copied 64 of 65 bytes");
+        assert!(!dest.exists());
+        assert!(!dir.join("1234567.vpk.part").exists());
+        assert_eq!(std::fs::read(&src).unwrap(), [1u8; 64]);
+
+        copy_verified(&src, &dest, 64).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), [1u8; 64]);
+        assert!(!dir.join("1234567.vpk.part").exists());
+    }
+
+    #[test]
+    fn undo_migrate_restores_addonlist() {
+        let dir = addons_dir("install_undo_migrate");
+        let original = dir.join("workshop").join("1234567.vpk");
+        std::fs::write(&original, b"addon").unwrap();
+        cache::save_missing_workshop_info(&original, 1234567, WorkshopStatus::Removed);
+        let mut list = AddonList::load(&dir).unwrap();
+        list.set("workshop\\1234567.vpk", false);
+        list.save().unwrap();
+
+        let dest = migrate(&original, MigrateOriginal::Keep).unwrap();
+        assert_eq!(dest, dir.join("1234567.vpk"));
+        let list = AddonList::load(&dir).unwrap();
+        assert_eq!(list.get("1234567.vpk"), Some(false));
+        assert!(cache::read_cache(&dest, 1234567).is_some());
+
+        undo_migrate(&dest).unwrap();
+        assert!(!dest.exists());
+        assert!(original.exists());
+        assert!(cache::read_cache(&dest, 1234567).is_none());
+        assert!(cache::read_cache(&original, 1234567).is_some());
+        let list = AddonList::load(&dir).unwrap();
+        assert_eq!(list.get("1234567.vpk"), None);
+        assert_eq!(list.get("workshop\\1234567.vpk"), Some(false));
+    }
+}
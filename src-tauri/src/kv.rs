@@ -0,0 +1,211 @@
+//! A lenient KeyValues reader for files written by hand, such as mission files and addoninfo.txt.
+//! Unlike keyvalues_serde, it never fails: keys are matched case-insensitively, unquoted tokens and
+//! comments are allowed, entries with a [$PLATFORM] conditional that is false on PC are dropped,
+//! and a missing closing brace ends the object
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KvValue {
+    Str(String),
+    Obj(KvObject)
+}
+
+impl KvValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KvValue::Str(value) => Some(value),
+            KvValue::Obj(_) => None
+        }
+    }
+
+    pub fn as_obj(&self) -> Option<&KvObject> {
+        match self {
+            KvValue::Obj(obj) => Some(obj),
+            KvValue::Str(_) => None
+        }
+    }
+}
+
+/// Entries in the order they appear in the file, duplicate keys are kept
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KvObject {
+    entries: Vec<(String, KvValue)>
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Open,
+    Close,
+    /// A [$PLATFORM] conditional, which applies to the entry before it
+    Cond(String)
+}
+
+/// Platforms that the conditional is false on, as the app only reads files for the PC game
+const CONSOLE_PLATFORMS: [&str; 3] = ["X360", "PS3", "GAMECONSOLE"];
+
+/// Whether an entry with the conditional, such as $X360 or !$X360 || $OSX, applies on PC
+fn is_conditional_true(cond: &str) -> bool {
+    cond.split("||").any(|term| {
+        let term = term.trim();
+        let (negated, name) = match term.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, term)
+        };
+        let is_console = CONSOLE_PLATFORMS.iter().any(|p| p.eq_ignore_ascii_case(name.trim().trim_start_matches('$')));
+        is_console == negated
+    })
+}
+
+fn tokenize(content: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {},
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            },
+            '[' => {
+                let mut cond = String::new();
+                while let Some(c) = chars.next_if(|c| *c != ']' && *c != '\n') {
+                    cond.push(c);
+                }
+                chars.next_if_eq(&']');
+                tokens.push(Token::Cond(cond));
+            },
+            // The game doesn't process escapes in these files, so backslashes in paths are kept as they are
+            '"' => {
+                let mut value = String::new();
+                while let Some(c) = chars.next_if(|c| *c != '"') {
+                    value.push(c);
+                }
+                chars.next();
+                tokens.push(Token::Str(value));
+            },
+            c => {
+                let mut value = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '{' | '}' | '"' | '[')) {
+                    value.push(c);
+                }
+                tokens.push(Token::Str(value));
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_object(tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> KvObject {
+    let mut obj = KvObject::default();
+    while let Some(token) = tokens.next() {
+        let key = match token {
+            Token::Str(key) => key,
+            Token::Close => break,
+            // A conditional with no entry before it
+            Token::Cond(_) => continue,
+            // A stray brace, read it as an object with no key
+            Token::Open => {
+                obj.entries.push((String::new(), KvValue::Obj(parse_object(tokens))));
+                continue;
+            }
+        };
+        // Conditionals can go after the key or the value
+        let mut applies = true;
+        if let Some(Token::Cond(cond)) = tokens.next_if(|token| matches!(token, Token::Cond(_))) {
+            applies &= is_conditional_true(&cond);
+        }
+        let value = if tokens.next_if_eq(&Token::Open).is_some() {
+            KvValue::Obj(parse_object(tokens))
+        } else if let Some(Token::Str(value)) = tokens.next_if(|token| matches!(token, Token::Str(_))) {
+            KvValue::Str(value)
+        } else {
+            // Key with no value before the object ends
+            KvValue::Str(String::new())
+        };
+        if let Some(Token::Cond(cond)) = tokens.next_if(|token| matches!(token, Token::Cond(_))) {
+            applies &= is_conditional_true(&cond);
+        }
+        if applies {
+            obj.entries.push((key, value));
+        }
+    }
+    obj
+}
+
+impl KvObject {
+    /// Parses a whole file, the returned object holds its root keys
+    pub fn parse(content: &str) -> KvObject {
+        let mut tokens = tokenize(content).into_iter().peekable();
+        let mut root = KvObject::default();
+        // Extra closing braces at the root are ignored
+        while tokens.peek().is_some() {
+            root.entries.extend(parse_object(&mut tokens).entries);
+        }
+        root
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &KvValue)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// First string value for the key, empty strings are treated as missing
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .find_map(|(_, value)| value.as_str())
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.get_str(key).map(|value| value.to_string())
+    }
+
+    pub fn get_obj(&self, key: &str) -> Option<&KvObject> {
+        self.entries.iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .find_map(|(_, value)| value.as_obj())
+    }
+
+    /// Reads 0/1, true/false and yes/no, None if the key is missing or not a boolean
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        parse_bool(self.get_str(key)?)
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        value => value.parse::<f64>().ok().map(|n| n != 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KvObject;
+
+    #[test]
+    fn keeps_backslashes() {
+        let root = KvObject::parse("\"Image\" \"maps\\foo\\\" \"Path\" \"C:\\new\" \"Next\" \"1\"");
+        assert_eq!(root.get_str("image"), Some("maps\\foo\\"));
+        assert_eq!(root.get_str("path"), Some("C:\\new"));
+        assert_eq!(root.get_str("next"), Some("1"));
+    }
+
+    #[test]
+    fn drops_console_only_entries() {
+        let root = KvObject::parse("Title Console [$X360]\nTitle PC [!$X360]\nOther Both [$X360 || $WIN32]\nGone x [$PS3]");
+        assert_eq!(root.get_str("title"), Some("PC"));
+        assert_eq!(root.get_str("other"), Some("Both"));
+        assert_eq!(root.get_str("gone"), None);
+    }
+
+    #[test]
+    fn reads_unclosed_objects() {
+        let root = KvObject::parse("\u{feff}Outer { Inner { Key value // comment\n");
+        let inner = root.get_obj("outer").and_then(|outer| outer.get_obj("INNER")).unwrap();
+        assert_eq!(inner.get_str("key"), Some("value"));
+    }
+}
@@ -3,12 +3,26 @@
   windows_subsystem = "windows"
 )]
 
+mod addonlist;
+mod archive;
+mod bulk;
+mod cache;
+mod collections;
 mod config;
+mod conflicts;
 mod util;
 mod commands;
 mod downloads;
+mod install;
+mod kv;
+mod profiles;
+mod retry;
+mod steam;
+mod thumbnails;
+mod trash;
+mod updates;
+mod vpk;
 
-use steam_workshop_api::{SteamWorkshop};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tauri::{Manager, State, Window};
@@ -20,11 +34,11 @@ use flexi_logger::{colored_default_format, FileSpec, Logger, WriteMode};
 use log::{debug, error, info, log, trace, warn};
 use crate::commands::{WORKSHOP_URL_REGEX};
 use crate::util::{WORKSHOP_ID_REGEX};
+use crate::downloads::{DownloadError, DownloadManager, DownloadState};
 
 pub struct Data {
   pub settings: Arc<Mutex<config::SettingsManager>>,
-  pub workshop: SteamWorkshop,
-  // pub downloads: DownloadManager // TODO: make download manager, queue downloads
+  pub downloads: Arc<Mutex<DownloadManager>>
 }
 
 struct SplashscreenWindow(Arc<Mutex<Window>>);
@@ -32,27 +46,26 @@ struct MainWindow(Arc<Mutex<Window>>);
 
 
 
-#[derive(Serialize, Deserialize, Clone)]
-enum ItemType {
-  Updateable,
-  Managed,
-  Unmanaged,
-  Unknown,
-  Workshop
-}
-
+/// Sent on the "progress" event
 #[derive(Serialize, Deserialize, Clone)]
 struct UpdatePayload {
+  job_id: u64,
   publishedfileid: u32,
-  bytes_downloaded: usize,
-  bytes_total: usize,
-  complete: bool
+  state: DownloadState,
+  bytes_downloaded: u64,
+  bytes_total: u64,
+  /// Current download speed
+  bytes_per_second: u64,
+  /// Estimated seconds left, if the total size is known
+  eta_seconds: Option<u64>
 }
 
+/// Sent on the "download-error" event
 #[derive(Serialize, Deserialize, Clone)]
 struct ErrorPayload {
+  job_id: u64,
   publishedfileid: u32,
-  error: String
+  error: DownloadError
 }
 
 #[tauri::command]
@@ -89,6 +102,7 @@ fn setup_logging() {
 fn main() {
   setup_logging();
   WORKSHOP_ID_REGEX.set(Regex::new(r"[0-9]{4,}").unwrap()).unwrap();
+  WORKSHOP_URL_REGEX.set(Regex::new(r"(?:steamcommunity\.com/(?:sharedfiles|workshop)/filedetails/?\?(?:[^#]*&)?id=|steam://url/CommunityFilePage/)([0-9]+)").unwrap()).unwrap();
   let mut settings = config::SettingsManager::new();
   if let Ok(false) = settings.load() {
     let gamedir = util::prompt_game_dir();
@@ -123,12 +137,19 @@ fn main() {
       // util::send_telemetry(&logger, downloads.size());
     }
 
-    let mut ws = SteamWorkshop::new();
-    ws.set_apikey(settings.get().steam_apikey.clone());
+    if let Err(e) = trash::enforce_retention(settings.get(), &[]) {
+      error!("Could not purge old addons from trash: {}", e);
+    }
+
+    let settings = Arc::new(Mutex::new(settings));
+    let downloads = Arc::new(Mutex::new(DownloadManager::new(settings.clone())));
+    // Pick up any downloads left over from last session
+    DownloadManager::process(&downloads, &app.handle());
+    updates::start_scheduler(app.handle(), settings.clone(), downloads.clone());
 
     app.manage(Data {
-      settings: Arc::new(Mutex::new(settings)),
-      workshop: ws,
+      settings,
+      downloads
     });
     debug!("done init.");
     app.get_window("splashscreen").unwrap().hide().ok();
@@ -144,8 +165,35 @@ fn main() {
     close_splashscreen,
     commands::search_workshop,
     commands::toggle_addon,
+    commands::get_trash,
+    commands::restore_addon,
+    commands::purge_trash,
     commands::delete_addon,
-    commands::migrate_addon
+    commands::migrate_addon,
+    commands::toggle_addons,
+    commands::delete_addons,
+    commands::migrate_addons,
+    commands::move_addons,
+    commands::import_addon,
+    commands::install_archive,
+    commands::download_addon,
+    commands::get_download_queue,
+    commands::cancel_download,
+    commands::pause_download,
+    commands::resume_download,
+    commands::check_updates,
+    commands::update_addons,
+    commands::refresh_workshop_cache,
+    commands::get_addon_state_mismatches,
+    commands::get_addon_conflicts,
+    commands::get_vpk_files,
+    commands::extract_vpk_files,
+    commands::get_profiles,
+    commands::create_profile,
+    commands::diff_profile,
+    commands::apply_profile,
+    commands::delete_profile,
+    commands::import_collection
   ])
   .run(tauri::generate_context!())
   .expect("error while running tauri application");
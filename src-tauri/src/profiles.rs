@@ -0,0 +1,296 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use crate::addonlist::{self, AddonList, ToggleMode};
+use crate::config::get_appdir;
+use crate::util;
+
+/// Identifies an addon in a profile, by workshop id if it has one so renamed files still match
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ProfileAddon {
+    WorkshopId(u32),
+    FileName(String)
+}
+
+impl ProfileAddon {
+    pub fn from_path(path: &Path) -> Self {
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let file_name = file_name.strip_suffix(".disabled").unwrap_or(&file_name);
+        match util::find_workshop_id_in_str(file_name) {
+            Some(id) => ProfileAddon::WorkshopId(id),
+            None => ProfileAddon::FileName(file_name.to_string())
+        }
+    }
+}
+
+/// A named set of enabled addons, every other addon is disabled when it's applied
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub created: u64,
+    pub enabled: Vec<ProfileAddon>
+}
+
+/// Changes needed to apply a profile
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ProfileDiff {
+    pub enable: Vec<PathBuf>,
+    pub disable: Vec<PathBuf>,
+    /// Addons in the profile that are not installed
+    pub missing: Vec<ProfileAddon>
+}
+
+fn get_profiles_dir() -> PathBuf {
+    let dir = get_appdir().join("profiles");
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir).ok();
+    }
+    dir
+}
+
+fn get_profile_path(name: &str) -> Result<PathBuf, String> {
+    if name.trim().is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') {
+        return Err("Profile names can only contain letters, numbers, spaces, - and _".to_string());
+    }
+    Ok(get_profiles_dir().join(format!("{}.json", name.trim())))
+}
+
+/// Every vpk in the addons and workshop folder
+fn get_installed(addons_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut paths = vec![];
+    for folder in [addons_dir.to_path_buf(), addons_dir.join("workshop")] {
+        if !folder.exists() { continue; }
+        paths.extend(util::get_vpks_in_folder(&folder)?.iter().map(|e| e.path()));
+    }
+    Ok(paths)
+}
+
+pub fn list() -> Result<Vec<Profile>, String> {
+    let mut profiles = vec![];
+    for entry in std::fs::read_dir(get_profiles_dir()).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().is_none_or(|ext| ext != "json") { continue; }
+        match std::fs::read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string())) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => error!("Could not read profile {:?}: {}", path, e)
+        }
+    }
+    Ok(profiles)
+}
+
+pub fn load(name: &str) -> Result<Profile, String> {
+    let path = get_profile_path(name)?;
+    if !path.exists() {
+        return Err(format!("No profile named \"{}\"", name));
+    }
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+/// Saves the currently enabled addons as a profile, replacing any profile with the same name
+pub fn create(name: &str, addons_dir: &Path) -> Result<Profile, String> {
+    let path = get_profile_path(name)?;
+    let list = addonlist::load_for_folder(addons_dir);
+    let enabled = get_installed(addons_dir)?.iter()
+        .filter(|path| addonlist::get_addon_state(list.as_ref(), path).enabled)
+        .map(|path| ProfileAddon::from_path(path))
+        .collect();
+    let profile = Profile {
+        name: name.trim().to_string(),
+        created: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        enabled
+    };
+    let content = serde_json::to_string(&profile).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(profile)
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    let path = get_profile_path(name)?;
+    if !path.exists() {
+        return Err(format!("No profile named \"{}\"", name));
+    }
+    std::fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+pub fn diff(profile: &Profile, addons_dir: &Path) -> Result<ProfileDiff, String> {
+    let list = addonlist::load_for_folder(addons_dir);
+    let mut diff = ProfileDiff::default();
+    let mut found: Vec<&ProfileAddon> = vec![];
+    for path in get_installed(addons_dir)? {
+        let addon = ProfileAddon::from_path(&path);
+        let should_enable = match profile.enabled.iter().find(|a| **a == addon) {
+            Some(a) => { found.push(a); true },
+            None => false
+        };
+        let enabled = addonlist::get_addon_state(list.as_ref(), &path).enabled;
+        if should_enable && !enabled {
+            diff.enable.push(path);
+        } else if !should_enable && enabled {
+            diff.disable.push(path);
+        }
+    }
+    diff.missing = profile.enabled.iter()
+        .filter(|a| !found.contains(a))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
+
+/// Makes the diff's changes, recording every rename in renamed so they can be undone
+fn apply_changes(diff: &ProfileDiff, mut list: Option<&mut AddonList>, renamed: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), String> {
+    let changes = diff.enable.iter().map(|p| (p, true))
+        .chain(diff.disable.iter().map(|p| (p, false)));
+    for (path, enable) in changes {
+        let mut path = path.clone();
+        // Addons disabled by name have to be renamed back for the game to see them, in either mode
+        let needs_rename = match list {
+            Some(_) => enable && !addonlist::is_file_enabled(&path),
+            None => enable != addonlist::is_file_enabled(&path)
+        };
+        if needs_rename {
+            let new_path = util::get_toggled_path(&path)?;
+            std::fs::rename(&path, &new_path)
+                .map_err(|e| format!("Could not rename {:?}: {}", path, e))?;
+            renamed.push((path, new_path.clone()));
+            path = new_path;
+        }
+        if let Some(list) = list.as_mut() {
+            let key = addonlist::get_list_key(&path)
+                .ok_or_else(|| format!("Could not find addonlist.txt entry for {:?}", path))?;
+            list.set(&key, enable);
+        }
+    }
+    Ok(())
+}
+
+/// Enables and disables addons to match the profile. If any change fails, all changes are undone
+pub fn apply(profile: &Profile, addons_dir: &Path, mode: ToggleMode) -> Result<ProfileDiff, String> {
+    let diff = diff(profile, addons_dir)?;
+    // Renames done so far, as (from, to)
+    let mut renamed: Vec<(PathBuf, PathBuf)> = vec![];
+    let result = match mode {
+        // addonlist.txt is only saved if every change worked
+        ToggleMode::AddonList => addonlist::update(addons_dir, |list| apply_changes(&diff, Some(list), &mut renamed)),
+        ToggleMode::Rename => apply_changes(&diff, None, &mut renamed)
+    };
+
+    if let Err(e) = result {
+        error!("Applying profile \"{}\" failed, rolling back {} renames: {}", profile.name, renamed.len(), e);
+        for (from, to) in renamed.iter().rev() {
+            if let Err(e) = std::fs::rename(to, from) {
+                error!("Could not roll back {:?} -> {:?}: {}", to, from, e);
+            }
+        }
+        return Err(e);
+    }
+    debug!("applied profile \"{}\": {} enabled, {} disabled", profile.name, diff.enable.len(), diff.disable.len());
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use regex::Regex;
+    use crate::addonlist::{AddonList, ToggleMode};
+    use crate::config::set_test_appdir;
+    use crate::util::WORKSHOP_ID_REGEX;
+    use super::{apply, create, diff, list, load, Profile, ProfileAddon};
+
+    /// Creates an addons folder holding the files, using its own app folder so there are no other profiles
+    fn addons_dir(name: &str, files: &[&str]) -> PathBuf {
+        // Set up by main
+        WORKSHOP_ID_REGEX.get_or_init(|| Regex::new(r"[0-9]{4,}").unwrap());
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        set_test_appdir(dir.join("appdir"));
+        let addons_dir = dir.join("addons");
+        std::fs::create_dir_all(addons_dir.join("workshop")).unwrap();
+        for file in files {
+            std::fs::write(addons_dir.join(file), file.as_bytes()).unwrap();
+        }
+        addons_dir
+    }
+
+    fn profile(enabled: &[&str]) -> Profile {
+        let enabled = enabled.iter().map(|name| ProfileAddon::from_path(&PathBuf::from(name))).collect();
+        Profile { name: "Test".to_string(), created: 0, enabled }
+    }
+
+    #[test]
+    fn create_diff_and_apply() {
+        let dir = addons_dir("profiles_apply", &["a.vpk", "b.vpk.disabled", "workshop/1234567.vpk"]);
+        assert!(create("../escape", &dir).is_err());
+        let created = create(" Test ", &dir).unwrap();
+        assert_eq!(created.name, "Test");
+        assert_eq!(created.enabled.len(), 2);
+        assert!(created.enabled.contains(&ProfileAddon::FileName("a.vpk".to_string())));
+        assert!(created.enabled.contains(&ProfileAddon::WorkshopId(1234567)));
+        assert_eq!(load("Test").unwrap().enabled, created.enabled);
+        assert_eq!(list().unwrap().len(), 1);
+
+        std::fs::rename(dir.join("a.vpk"), dir.join("a.vpk.disabled")).unwrap();
+        std::fs::rename(dir.join("b.vpk.disabled"), dir.join("b.vpk")).unwrap();
+        // Matched by workshop id, even though the file was renamed
+        std::fs::rename(dir.join("workshop").join("1234567.vpk"), dir.join("workshop").join("1234567_v2.vpk")).unwrap();
+        let changes = diff(&created, &dir).unwrap();
+        assert_eq!(changes.enable, [dir.join("a.vpk.disabled")]);
+        assert_eq!(changes.disable, [dir.join("b.vpk")]);
+        assert!(changes.missing.is_empty());
+
+        apply(&created, &dir, ToggleMode::Rename).unwrap();
+        assert!(dir.join("a.vpk").exists());
+        assert!(dir.join("b.vpk.disabled").exists());
+        let changes = diff(&created, &dir).unwrap();
+        assert!(changes.enable.is_empty() && changes.disable.is_empty());
+
+        std::fs::remove_file(dir.join("workshop").join("1234567_v2.vpk")).unwrap();
+        assert_eq!(diff(&created, &dir).unwrap().missing, [ProfileAddon::WorkshopId(1234567)]);
+    }
+
+    #[test]
+    fn apply_with_addonlist() {
+        let dir = addons_dir("profiles_addonlist", &["a.vpk", "b.vpk", "c.vpk.disabled"]);
+        let mut addon_list = AddonList::load(&dir).unwrap();
+        addon_list.set("b.vpk", false);
+        addon_list.save().unwrap();
+
+        let applied = apply(&profile(&["b.vpk", "c.vpk"]), &dir, ToggleMode::AddonList).unwrap();
+        assert_eq!(applied.enable.len(), 2);
+        assert_eq!(applied.disable, [dir.join("a.vpk")]);
+        // Only addons disabled by name are renamed, the rest are left to addonlist.txt
+        assert!(dir.join("a.vpk").exists());
+        assert!(dir.join("c.vpk").exists());
+        let addon_list = AddonList::load(&dir).unwrap();
+        assert_eq!(addon_list.get("a.vpk"), Some(false));
+        assert_eq!(addon_list.get("b.vpk"), Some(true));
+        assert_eq!(addon_list.get("c.vpk"), Some(true));
+    }
+
+    #[test]
+    fn failed_apply_rolls_back() {
+        let dir = addons_dir("profiles_rollback", &["a.vpk.disabled", "b.vpk", "workshop/1234567.vpk.disabled"]);
+        // A folder where the addon is renamed to makes its rename fail, after a.vpk has been renamed
+        std::fs::create_dir_all(dir.join("workshop").join("1234567.vpk").join("blocker")).unwrap();
+        let mut addon_list = AddonList::load(&dir).unwrap();
+        addon_list.set("b.vpk", true);
+        addon_list.save().unwrap();
+        let list_path = dir.parent().unwrap().join("addonlist.txt");
+        let list_before = std::fs::read_to_string(&list_path).unwrap();
+
+        let profile = profile(&["a.vpk", "1234567.vpk"]);
+        for mode in [ToggleMode::AddonList, ToggleMode::Rename] {
+            let err = apply(&profile, &dir, mode).err().expect("apply should fail");
+            assert!(err.contains("Could not rename"), "{}", err);
+            assert!(dir.join("a.vpk.disabled").exists(), "{:?} did not roll back", mode);
+            assert!(!dir.join("a.vpk").exists());
+            assert!(dir.join("b.vpk").exists());
+            assert!(dir.join("workshop").join("1234567.vpk.disabled").exists());
+            assert_eq!(std::fs::read_to_string(&list_path).unwrap(), list_before, "{:?} saved addonlist.txt", mode);
+        }
+    }
+}
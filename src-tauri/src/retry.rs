@@ -0,0 +1,143 @@
+use std::collections::hash_map::RandomState;
+use std::fmt::Display;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use log::warn;
+use reqwest::header::{self, HeaderMap};
+use serde::{Deserialize, Serialize};
+
+/// What to do after a failed attempt
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Retry {
+    /// Trying again won't help, such as a bad request or api key
+    Never,
+    /// Try again after the backoff, or the server's Retry-After if it's longer
+    After(Option<Duration>)
+}
+
+/// Seconds in a 429 response's Retry-After header. It can also be a http date, but steam only sends seconds
+pub fn get_retry_after(headers: &HeaderMap) -> Option<u64> {
+    headers.get(header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// How failed steam requests and downloads are retried
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// How much of the delay is randomized, from 0.0 (none) to 1.0
+    pub jitter: f64
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            jitter: 0.25
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay to wait before the given retry (starting at 1).
+    /// A server provided Retry-After is respected if it's longer than the backoff, up to max_delay_ms
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16);
+        let backoff = self.initial_delay_ms.saturating_mul(1 << exponent).min(self.max_delay_ms);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // Scale randomly between (1 - jitter) and (1 + jitter)
+        let factor = 1.0 - jitter + (2.0 * jitter * random_unit());
+        let backoff = Duration::from_millis((backoff as f64 * factor) as u64);
+        match retry_after {
+            Some(retry_after) if retry_after > backoff => retry_after.min(Duration::from_millis(self.max_delay_ms)),
+            _ => backoff
+        }
+    }
+
+    /// Runs the blocking function until it succeeds, classify says an error can't be retried, or it runs out of attempts.
+    /// Sleeps between attempts, so it should not be called from an async task
+    pub fn run<T, E: Display>(&self, name: &str, classify: impl Fn(&E) -> Retry, mut func: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match func() {
+                Err(e) if attempt < self.max_attempts => {
+                    let Retry::After(retry_after) = classify(&e) else { return Err(e) };
+                    let delay = self.delay(attempt, retry_after);
+                    warn!("{} failed (attempt {}/{}), retrying in {:?}: {}", name, attempt, self.max_attempts, delay, e);
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+}
+
+/// Random number between 0.0 and 1.0
+fn random_unit() -> f64 {
+    let value = RandomState::new().build_hasher().finish();
+    (value as f64) / (u64::MAX as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Retry, RetryPolicy};
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy { max_attempts: 4, initial_delay_ms: 100, max_delay_ms: 1000, jitter }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = policy(0.0);
+        let delays: Vec<u128> = (1..=6).map(|retry| policy.delay(retry, None).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let policy = policy(0.5);
+        for _ in 0..200 {
+            let delay = policy.delay(2, None).as_millis();
+            assert!((100..=300).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn retry_after_is_clamped() {
+        let policy = policy(0.0);
+        assert_eq!(policy.delay(1, Some(Duration::from_millis(500))), Duration::from_millis(500));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), Duration::from_millis(1000));
+        assert_eq!(policy.delay(3, Some(Duration::from_millis(10))), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn non_retryable_error_returns_at_once() {
+        let mut attempts = 0;
+        let result: Result<(), &str> = policy(0.0).run("test", |_| Retry::Never, || {
+            attempts += 1;
+            Err("bad request")
+        });
+        assert_eq!(result, Err("bad request"));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let policy = RetryPolicy { initial_delay_ms: 1, max_delay_ms: 1, ..policy(0.0) };
+        let mut attempts = 0;
+        let result: Result<u32, &str> = policy.run("test", |_| Retry::After(None), || {
+            attempts += 1;
+            if attempts < 3 { Err("timed out") } else { Ok(attempts) }
+        });
+        assert_eq!(result, Ok(3));
+    }
+}
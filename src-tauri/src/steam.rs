@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use log::{debug, warn};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use crate::cache::WorkshopStatus;
+use crate::config::Settings;
+use crate::retry::{self, Retry, RetryPolicy};
+
+/// Used when settings do not specify steam_api_url
+pub const STEAM_API_URL: &str = "https://api.steampowered.com";
+/// Collections can contain other collections, stop following them after this many levels
+const MAX_COLLECTION_DEPTH: u8 = 4;
+
+// https://partner.steamgames.com/doc/api/steam_api#EResult
+const RESULT_OK: i32 = 1;
+const RESULT_FILE_NOT_FOUND: i32 = 9;
+const RESULT_ACCESS_DENIED: i32 = 15;
+
+// https://partner.steamgames.com/doc/api/ISteamRemoteStorage#EWorkshopFileType
+const FILE_TYPE_COLLECTION: u32 = 2;
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    response: T
+}
+
+/// A failed call to the Steam Web API
+enum ApiError {
+    Request(reqwest::Error),
+    Status(StatusCode, Option<u64>)
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Request(e) => write!(f, "{}", e),
+            ApiError::Status(status, _) => write!(f, "Steam responded with status {}", status)
+        }
+    }
+}
+
+impl ApiError {
+    fn retry(&self) -> Retry {
+        match self {
+            // A response that doesn't parse will be the same next time
+            ApiError::Request(e) if e.is_decode() => Retry::Never,
+            ApiError::Request(_) => Retry::After(None),
+            ApiError::Status(StatusCode::TOO_MANY_REQUESTS, retry_after_secs) => Retry::After(retry_after_secs.map(Duration::from_secs)),
+            ApiError::Status(status, _) if status.is_server_error() => Retry::After(None),
+            ApiError::Status(..) => Retry::Never
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DetailsResponse {
+    #[serde(default)]
+    publishedfiledetails: Vec<serde_json::Value>
+}
+
+/// Response of GetPublishedFileDetails
+#[derive(Default)]
+pub struct FileDetails {
+    /// Items that are live on the workshop
+    pub items: Vec<WorkshopItem>,
+    /// Status of every item steam returned, live or not
+    pub statuses: HashMap<u32, WorkshopStatus>
+}
+
+#[derive(Deserialize)]
+struct CollectionResponse {
+    #[serde(default)]
+    collectiondetails: Vec<CollectionResult>
+}
+#[derive(Deserialize)]
+struct CollectionResult {
+    publishedfileid: String,
+    result: i32,
+    #[serde(default)]
+    children: Vec<CollectionChild>
+}
+#[derive(Deserialize)]
+struct CollectionChild {
+    publishedfileid: String,
+    #[serde(default)]
+    filetype: u32
+}
+
+/// Order of workshop search results
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SearchSort {
+    /// Best match for the search text, or trending if there is none
+    #[default]
+    Relevance,
+    MostPopular,
+    MostRecent,
+    Trending,
+    MostSubscribed,
+    LastUpdated
+}
+
+impl SearchSort {
+    /// EPublishedFileQueryType, https://partner.steamgames.com/doc/webapi/IPublishedFileService#EPublishedFileQueryType
+    fn query_type(&self, has_text: bool) -> u32 {
+        match self {
+            // RankedByTextSearch
+            SearchSort::Relevance if has_text => 12,
+            // RankedByTrend
+            SearchSort::Relevance | SearchSort::Trending => 3,
+            // RankedByVote
+            SearchSort::MostPopular => 0,
+            // RankedByPublicationDate
+            SearchSort::MostRecent => 1,
+            // RankedByTotalUniqueSubscriptions
+            SearchSort::MostSubscribed => 9,
+            // RankedByLastUpdatedDate
+            SearchSort::LastUpdated => 21
+        }
+    }
+}
+
+pub struct SearchQuery {
+    pub text: String,
+    /// Items must have all of these tags, such as "Campaigns" or "Survivors"
+    pub required_tags: Vec<String>,
+    pub excluded_tags: Vec<String>,
+    pub sort: SearchSort,
+    pub page_size: u32,
+    /// next_cursor of the previous page, None for the first page
+    pub cursor: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SearchPage {
+    pub items: Vec<WorkshopItem>,
+    pub total: u64,
+    /// Pass back in to get the next page, None when there are no more results
+    pub next_cursor: Option<String>
+}
+
+#[derive(Deserialize)]
+struct QueryFilesResponse {
+    #[serde(default)]
+    total: u64,
+    #[serde(default)]
+    publishedfiledetails: Vec<WorkshopItem>,
+    next_cursor: Option<String>
+}
+
+/// Calls to the Steam Web API that steam_workshop_api does not expose
+pub struct SteamApi {
+    client: reqwest::blocking::Client,
+    base_url: String,
+    retry: RetryPolicy,
+    apikey: Option<String>
+}
+
+impl SteamApi {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            base_url: settings.steam_api_url.clone()
+                .unwrap_or_else(|| STEAM_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            retry: settings.retry.clone(),
+            apikey: settings.steam_apikey.clone()
+        }
+    }
+
+    /// Sends the request built by build, retrying on network errors, 5xx and 429 responses
+    fn send<T: DeserializeOwned>(&self, method: &str, build: impl Fn(&str) -> reqwest::blocking::RequestBuilder) -> Result<T, String> {
+        let url = format!("{}/{}", self.base_url, method);
+        let response: ApiResponse<T> = self.retry.run(method, ApiError::retry, || {
+            let response = build(&url).send().map_err(ApiError::Request)?;
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                return Err(ApiError::Status(status, retry::get_retry_after(response.headers())));
+            } else if !status.is_success() {
+                return Err(ApiError::Status(status, None));
+            }
+            response.json().map_err(ApiError::Request)
+        }).map_err(|e| e.to_string())?;
+        Ok(response.response)
+    }
+
+    fn get<T: DeserializeOwned>(&self, method: &str, query: &[(String, String)]) -> Result<T, String> {
+        self.send(method, |url| self.client.get(url).query(query))
+    }
+
+    fn post<T: DeserializeOwned>(&self, method: &str, form: &[(String, String)]) -> Result<T, String> {
+        self.send(method, |url| self.client.post(url).form(form))
+    }
+
+    /// Gets the details and status of up to 100 items. Items that are private or deleted only have a status
+    pub fn get_file_details(&self, ids: &[u32]) -> Result<FileDetails, String> {
+        let mut form: Vec<(String, String)> = vec![("itemcount".to_string(), ids.len().to_string())];
+        for (i, id) in ids.iter().enumerate() {
+            form.push((format!("publishedfileids[{}]", i), id.to_string()));
+        }
+        let response: DetailsResponse = self.post("ISteamRemoteStorage/GetPublishedFileDetails/v1/", &form)?;
+        let mut details = FileDetails::default();
+        for raw in response.publishedfiledetails {
+            let Some(id) = raw.get("publishedfileid").and_then(|id| id.as_str()).and_then(|id| id.parse().ok()) else { continue; };
+            let result = raw.get("result").and_then(|r| r.as_i64()).unwrap_or(0) as i32;
+            details.statuses.insert(id, match result {
+                RESULT_OK => WorkshopStatus::Live,
+                RESULT_FILE_NOT_FOUND => WorkshopStatus::Removed,
+                RESULT_ACCESS_DENIED => WorkshopStatus::Private,
+                _ => WorkshopStatus::Unknown
+            });
+            // Missing items only have publishedfileid and result, so they can't be read as a WorkshopItem
+            if result != RESULT_OK { continue; }
+            match serde_json::from_value(raw) {
+                Ok(item) => details.items.push(item),
+                Err(e) => warn!("Could not read workshop item details for {}: {}", id, e)
+            }
+        }
+        Ok(details)
+    }
+
+    /// Gets the details of up to 100 items, items that are private or deleted are left out
+    pub fn get_published_file_details(&self, ids: &[u32]) -> Result<Vec<WorkshopItem>, String> {
+        self.get_file_details(ids).map(|details| details.items)
+    }
+
+    /// Gets the ids of every item in the collection, including items of any collections inside it
+    pub fn get_collection_items(&self, collection_id: u32) -> Result<Vec<u32>, String> {
+        let mut items: Vec<u32> = vec![];
+        let mut seen: HashSet<u32> = HashSet::from([collection_id]);
+        let mut pending = vec![collection_id];
+        let mut depth = 0;
+        while !pending.is_empty() && depth < MAX_COLLECTION_DEPTH {
+            let mut form: Vec<(String, String)> = vec![("collectioncount".to_string(), pending.len().to_string())];
+            for (i, id) in pending.iter().enumerate() {
+                form.push((format!("publishedfileids[{}]", i), id.to_string()));
+            }
+            let response: CollectionResponse = self.post("ISteamRemoteStorage/GetCollectionDetails/v1/", &form)?;
+            pending.clear();
+            for collection in response.collectiondetails {
+                if collection.result != RESULT_OK {
+                    // Only the top level collection not existing is an error
+                    if collection.publishedfileid == collection_id.to_string() {
+                        return Err(format!("Collection {} could not be found, it may be private or deleted", collection_id));
+                    }
+                    warn!("skipping nested collection {}: result {}", collection.publishedfileid, collection.result);
+                    continue;
+                }
+                for child in collection.children {
+                    let Ok(id) = child.publishedfileid.parse::<u32>() else { continue; };
+                    if !seen.insert(id) { continue; }
+                    if child.filetype == FILE_TYPE_COLLECTION {
+                        pending.push(id);
+                    } else {
+                        items.push(id);
+                    }
+                }
+            }
+            depth += 1;
+        }
+        debug!("collection {} has {} items", collection_id, items.len());
+        Ok(items)
+    }
+
+    /// Searches L4D2's workshop, requires a steam api key
+    pub fn search(&self, search: &SearchQuery) -> Result<SearchPage, String> {
+        let apikey = self.apikey.as_ref()
+            .ok_or_else(|| "A steam api key is required to search the workshop".to_string())?;
+        let text = search.text.trim();
+        let mut query: Vec<(String, String)> = vec![
+            ("key".to_string(), apikey.clone()),
+            ("appid".to_string(), "550".to_string()),
+            ("query_type".to_string(), search.sort.query_type(!text.is_empty()).to_string()),
+            ("numperpage".to_string(), search.page_size.clamp(1, 100).to_string()),
+            ("cursor".to_string(), search.cursor.clone().unwrap_or_else(|| "*".to_string())),
+            ("return_tags".to_string(), "true".to_string()),
+            ("return_previews".to_string(), "true".to_string()),
+            ("return_short_description".to_string(), "false".to_string()),
+            ("match_all_tags".to_string(), "true".to_string())
+        ];
+        if !text.is_empty() {
+            query.push(("search_text".to_string(), text.to_string()));
+        }
+        for (i, tag) in search.required_tags.iter().enumerate() {
+            query.push((format!("requiredtags[{}]", i), tag.clone()));
+        }
+        for (i, tag) in search.excluded_tags.iter().enumerate() {
+            query.push((format!("excludedtags[{}]", i), tag.clone()));
+        }
+        let response: QueryFilesResponse = self.get("IPublishedFileService/QueryFiles/v1/", &query)?;
+        // Steam keeps returning the same cursor once it's out of results
+        let next_cursor = response.next_cursor
+            .filter(|next| !response.publishedfiledetails.is_empty() && Some(next) != search.cursor.as_ref());
+        Ok(SearchPage {
+            items: response.publishedfiledetails,
+            total: response.total,
+            next_cursor
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchSort;
+
+    #[test]
+    fn query_types() {
+        assert_eq!(SearchSort::Relevance.query_type(true), 12);
+        assert_eq!(SearchSort::Relevance.query_type(false), 3);
+        assert_eq!(SearchSort::Trending.query_type(true), 3);
+        assert_eq!(SearchSort::MostPopular.query_type(false), 0);
+        assert_eq!(SearchSort::MostRecent.query_type(false), 1);
+        assert_eq!(SearchSort::MostSubscribed.query_type(false), 9);
+        assert_eq!(SearchSort::LastUpdated.query_type(false), 21);
+    }
+}
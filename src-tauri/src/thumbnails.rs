@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::cache::CACHE_FOLDER_NAME;
+use crate::util;
+use crate::vpk::{self, Vpk};
+
+/// Folder inside .addon_manager that holds a folder of images for each addon
+const THUMBNAILS_FOLDER_NAME: &str = "thumbnails";
+
+/// Images extracted from an addon, cached in .addon_manager/thumbnails/<addon>/
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Thumbnails {
+    /// addonimage from the vpk, or the .jpg next to the vpk
+    pub addon: Option<PathBuf>,
+    /// Chapter's Image from its mission file, to the png converted from its vtf
+    pub chapters: BTreeMap<String, PathBuf>
+}
+
+/// Contents of index.json, the vpk's modified time is kept so images are only extracted again when it changes
+#[derive(Serialize, Deserialize)]
+struct ThumbnailIndex {
+    modified: u64,
+    thumbnails: Thumbnails
+}
+
+/// File name of the addon without .vpk or .disabled, so thumbnails are kept when it's toggled
+fn get_addon_key(addon_path: &Path) -> String {
+    let file_name = addon_path.file_name().unwrap().to_string_lossy();
+    let file_name = file_name.strip_suffix(".disabled").unwrap_or(&file_name);
+    file_name.strip_suffix(".vpk").unwrap_or(file_name).to_string()
+}
+
+fn get_thumbnails_dir(folder: &Path) -> PathBuf {
+    folder.join(CACHE_FOLDER_NAME).join(THUMBNAILS_FOLDER_NAME)
+}
+
+fn convert_vtf(data: &[u8], dest: &Path) -> Result<(), String> {
+    // vtf only reads from a Vec
+    let bytes = data.to_vec();
+    let vtf = vtf::from_bytes(&bytes).map_err(|e| format!("Could not read vtf: {}", e))?;
+    let image = vtf.highres_image.decode(0).map_err(|e| format!("Could not decode vtf: {}", e))?;
+    image.save(dest).map_err(|e| e.to_string())
+}
+
+fn extract_thumbnails(addon_path: &Path, dir: &Path, chapter_images: &[String]) -> Result<Thumbnails, String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut vpk = Vpk::open(addon_path)?;
+    // Normalized path -> path in the tree
+    let tree_paths: HashMap<String, String> = vpk.tree_paths().into_iter()
+        .map(|path| (vpk::normalize_path(&path), path))
+        .collect();
+    let mut thumbnails = Thumbnails::default();
+
+    let sibling_image = addon_path.with_file_name(format!("{}.jpg", get_addon_key(addon_path)));
+    if let Some(tree_path) = tree_paths.get("addonimage.jpg") {
+        let dest = dir.join("addonimage.jpg");
+        std::fs::write(&dest, vpk.read(tree_path)?).map_err(|e| e.to_string())?;
+        thumbnails.addon = Some(dest);
+    } else if let Some(tree_path) = tree_paths.get("addonimage.vtf") {
+        let dest = dir.join("addonimage.png");
+        convert_vtf(&vpk.read(tree_path)?, &dest)?;
+        thumbnails.addon = Some(dest);
+    } else if sibling_image.exists() {
+        // Steam puts the workshop preview next to the vpk
+        let dest = dir.join("addonimage.jpg");
+        std::fs::copy(&sibling_image, &dest).map_err(|e| e.to_string())?;
+        thumbnails.addon = Some(dest);
+    }
+
+    for image in chapter_images {
+        // Chapter images are materials, relative to materials/vgui
+        let image_path = image.replace('\\', "/").to_lowercase();
+        let Some(tree_path) = tree_paths.get(&format!("materials/vgui/{}.vtf", image_path)) else { continue; };
+        let dest = dir.join(format!("{}.png", image_path.replace('/', "_")));
+        match vpk.read(tree_path).and_then(|data| convert_vtf(&data, &dest)) {
+            Ok(_) => { thumbnails.chapters.insert(image.clone(), dest); },
+            Err(e) => warn!("Could not convert chapter image {} of {:?}: {}", image, addon_path, e)
+        }
+    }
+    Ok(thumbnails)
+}
+
+/// Gets the addon's thumbnails, extracting them if the vpk changed since they were last cached
+pub fn get_thumbnails(addon_path: &Path, chapter_images: &[String]) -> Thumbnails {
+    let Some(folder) = addon_path.parent() else { return Thumbnails::default() };
+    let dir = get_thumbnails_dir(folder).join(get_addon_key(addon_path));
+    let index_path = dir.join("index.json");
+    let modified = addon_path.metadata().ok()
+        .and_then(|meta| meta.modified().ok())
+        .map_or(0, |time| time.duration_since(UNIX_EPOCH).unwrap().as_secs());
+    let index: Option<ThumbnailIndex> = std::fs::read_to_string(&index_path).ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    if let Some(index) = index.filter(|index| index.modified == modified) {
+        return index.thumbnails
+    }
+
+    debug!("extracting thumbnails of {:?}", addon_path);
+    match extract_thumbnails(addon_path, &dir, chapter_images) {
+        Ok(thumbnails) => {
+            let index = ThumbnailIndex { modified, thumbnails };
+            if let Err(e) = std::fs::write(&index_path, serde_json::to_string(&index).unwrap()) {
+                warn!("Could not save thumbnail index for {:?}: {}", addon_path, e);
+            }
+            index.thumbnails
+        },
+        Err(e) => {
+            warn!("Could not extract thumbnails of {:?}: {}", addon_path, e);
+            Thumbnails::default()
+        }
+    }
+}
+
+/// Deletes thumbnails in the folder's .addon_manager that no longer have an addon, returning how many were removed
+pub fn remove_orphaned(folder: &Path) -> Result<usize, String> {
+    let dir = get_thumbnails_dir(folder);
+    if !dir.exists() {
+        return Ok(0)
+    }
+    let installed: HashSet<String> = util::get_vpks_in_folder(folder)?.iter()
+        .map(|entry| get_addon_key(&entry.path()))
+        .collect();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.is_dir() || installed.contains(&*path.file_name().unwrap().to_string_lossy()) { continue; }
+        debug!("removing orphaned thumbnails {:?}", path);
+        if std::fs::remove_dir_all(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+    use crate::vpk::write_test_vpk;
+    use super::{convert_vtf, get_thumbnails, remove_orphaned};
+
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+    /// A 7.1 vtf holding a single RGB888 image, with no mipmaps or low res image
+    fn build_vtf(width: u16, height: u16) -> Vec<u8> {
+        let mut vtf = vec![];
+        vtf.extend(b"VTF\0");
+        vtf.extend(7u32.to_le_bytes());
+        vtf.extend(1u32.to_le_bytes());
+        vtf.extend(64u32.to_le_bytes()); // header size
+        vtf.extend(width.to_le_bytes());
+        vtf.extend(height.to_le_bytes());
+        vtf.extend(0u32.to_le_bytes()); // flags
+        vtf.extend(1u16.to_le_bytes()); // frames
+        vtf.extend(0u16.to_le_bytes()); // first frame
+        vtf.extend([0; 4]);
+        vtf.extend([0; 12]); // reflectivity
+        vtf.extend([0; 4]);
+        vtf.extend(1f32.to_le_bytes()); // bumpmap scale
+        vtf.extend(2u32.to_le_bytes()); // RGB888
+        vtf.push(1); // mipmap count
+        vtf.extend((-1i32).to_le_bytes()); // no low res image
+        vtf.extend([0, 0]);
+        vtf.resize(64, 0);
+        vtf.extend((0..width as usize * height as usize * 3).map(|i| i as u8));
+        vtf
+    }
+
+    /// Creates an empty folder of addons
+    fn addons_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Rewrites the vpk, with a later modified time so cached thumbnails are out of date
+    fn rewrite_vpk(path: &Path, files: &[(&str, &[u8])]) {
+        write_test_vpk(path, files);
+        let modified = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn converts_vtf_to_png() {
+        let dir = addons_dir("thumbnails_convert");
+        let dest = dir.join("image.png");
+        convert_vtf(&build_vtf(4, 2), &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap()[..8], PNG_SIGNATURE);
+
+        let err = convert_vtf(b"not a vtf", &dir.join("bad.png")).unwrap_err();
+        assert!(err.starts_with("Could not read vtf"), "{}", err);
+        assert!(!dir.join("bad.png").exists());
+    }
+
+    #[test]
+    fn extracts_and_caches_thumbnails() {
+        let dir = addons_dir("thumbnails_cache");
+        let addon = dir.join("campaign.vpk");
+        let vtf = build_vtf(4, 4);
+        write_test_vpk(&addon, &[
+            ("addonimage.jpg", b"first"),
+            ("materials/vgui/chapters/ch1.vtf", &vtf)
+        ]);
+        let chapter_images = vec!["chapters\\ch1".to_string(), "chapters/missing".to_string()];
+
+        let thumbnails = get_thumbnails(&addon, &chapter_images);
+        let addon_image = thumbnails.addon.unwrap();
+        assert_eq!(std::fs::read(&addon_image).unwrap(), b"first");
+        assert_eq!(thumbnails.chapters.keys().collect::<Vec<_>>(), ["chapters\\ch1"]);
+        assert_eq!(std::fs::read(&thumbnails.chapters["chapters\\ch1"]).unwrap()[..8], PNG_SIGNATURE);
+
+        // Unchanged vpks use the index, even after being disabled
+        std::fs::write(&addon_image, b"edited").unwrap();
+        let disabled = dir.join("campaign.vpk.disabled");
+        std::fs::rename(&addon, &disabled).unwrap();
+        assert_eq!(get_thumbnails(&disabled, &chapter_images).addon.unwrap(), addon_image);
+        assert_eq!(std::fs::read(&addon_image).unwrap(), b"edited");
+
+        // Changed vpks are extracted again
+        rewrite_vpk(&disabled, &[("addonimage.jpg", b"second")]);
+        let thumbnails = get_thumbnails(&disabled, &chapter_images);
+        assert_eq!(std::fs::read(thumbnails.addon.unwrap()).unwrap(), b"second");
+        assert!(thumbnails.chapters.is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_image_next_to_vpk() {
+        let dir = addons_dir("thumbnails_sibling");
+        let addon = dir.join("1234567.vpk");
+        write_test_vpk(&addon, &[("addoninfo.txt", b"")]);
+        assert!(get_thumbnails(&addon, &[]).addon.is_none());
+
+        std::fs::write(dir.join("1234567.jpg"), b"preview").unwrap();
+        rewrite_vpk(&addon, &[("addoninfo.txt", b"")]);
+        let thumbnails = get_thumbnails(&addon, &[]);
+        assert_eq!(std::fs::read(thumbnails.addon.unwrap()).unwrap(), b"preview");
+    }
+
+    #[test]
+    fn removes_orphaned_thumbnails() {
+        let dir = addons_dir("thumbnails_orphaned");
+        for name in ["kept.vpk", "removed.vpk"] {
+            write_test_vpk(&dir.join(name), &[("addonimage.jpg", b"image")]);
+            assert!(get_thumbnails(&dir.join(name), &[]).addon.is_some());
+        }
+        std::fs::remove_file(dir.join("removed.vpk")).unwrap();
+        assert_eq!(remove_orphaned(&dir).unwrap(), 1);
+        assert_eq!(remove_orphaned(&dir).unwrap(), 0);
+        assert!(get_thumbnails(&dir.join("kept.vpk"), &[]).addon.unwrap().exists());
+    }
+}
@@ -0,0 +1,268 @@
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use crate::cache;
+use crate::config::{get_appdir, Settings};
+use crate::install;
+use crate::util;
+
+/// Used when settings do not specify trash_max_size_mb
+pub const DEFAULT_TRASH_MAX_SIZE_MB: u64 = 10 * 1024;
+/// Used when settings do not specify trash_max_age_days
+pub const DEFAULT_TRASH_MAX_AGE_DAYS: u64 = 30;
+
+/// Added to trash ids, as addons deleted at the same time can get the same timestamp
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A deleted addon. Each one is kept in trash/<id>/, along with its .addon_manager folder and this as trash.json
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Where the addon was deleted from, and is restored to
+    pub original_path: PathBuf,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Unix timestamp of when it was deleted
+    pub deleted_at: u64,
+    pub workshop_id: Option<u32>,
+    /// Title from the cached workshop info, if it had any
+    pub title: Option<String>
+}
+
+impl TrashEntry {
+    fn get_dir(&self) -> PathBuf {
+        get_trash_dir().join(&self.id)
+    }
+
+    /// Path of the deleted addon inside the trash
+    pub fn get_path(&self) -> PathBuf {
+        self.get_dir().join(&self.file_name)
+    }
+}
+
+fn get_trash_dir() -> PathBuf {
+    let dir = get_appdir().join("trash");
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir).ok();
+    }
+    dir
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Moves the addon into the trash. Retention limits are not applied, call enforce_retention after
+pub fn trash(path: &Path) -> Result<TrashEntry, String> {
+    if !path.is_file() {
+        return Err(format!("File does not exist at {:?}", path));
+    }
+    let workshop_id = path.file_stem().and_then(|stem| util::find_workshop_id_in_str(&stem.to_string_lossy()));
+    let entry = TrashEntry {
+        id: format!("{}-{}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos(), NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+        original_path: path.to_path_buf(),
+        file_name: path.file_name().unwrap().to_string_lossy().to_string(),
+        file_size: path.metadata().map_err(|e| e.to_string())?.len(),
+        deleted_at: now(),
+        workshop_id,
+        title: workshop_id.and_then(|id| cache::get_cached_workshop_info(path, id)).map(|item| item.title)
+    };
+    let dir = entry.get_dir();
+    // Fails if the folder exists, so another addon's folder is never shared
+    std::fs::create_dir(&dir).map_err(|e| format!("Could not create trash folder {:?}: {}", dir, e))?;
+    let content = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    std::fs::write(dir.join("trash.json"), content).map_err(|e| e.to_string())?;
+    debug!("moving {:?} to trash {}", path, entry.id);
+    // Brings the addon's cache file along with it
+    if let Err(e) = install::move_addon(path, &dir) {
+        std::fs::remove_dir_all(&dir).ok();
+        return Err(e);
+    }
+    Ok(entry)
+}
+
+/// Every addon in the trash, newest first
+pub fn list() -> Result<Vec<TrashEntry>, String> {
+    let mut entries: Vec<TrashEntry> = vec![];
+    for dir in std::fs::read_dir(get_trash_dir()).map_err(|e| e.to_string())? {
+        let path = dir.map_err(|e| e.to_string())?.path().join("trash.json");
+        match std::fs::read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string())) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => error!("Could not read trash entry {:?}: {}", path, e)
+        }
+    }
+    entries.sort_by_key(|entry| Reverse(entry.deleted_at));
+    Ok(entries)
+}
+
+fn get(id: &str) -> Result<TrashEntry, String> {
+    list()?.into_iter()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| format!("No deleted addon with id {}", id))
+}
+
+/// Moves the addon back to where it was deleted from, returning its path
+pub fn restore(id: &str) -> Result<PathBuf, String> {
+    let entry = get(id)?;
+    let folder = entry.original_path.parent()
+        .ok_or_else(|| "Addon has no original folder".to_string())?;
+    if !folder.exists() {
+        std::fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    }
+    let path = install::move_addon(&entry.get_path(), folder)?;
+    debug!("restored {} to {:?}", entry.id, path);
+    std::fs::remove_dir_all(entry.get_dir()).ok();
+    Ok(path)
+}
+
+/// Permanently deletes the addon from the trash, or everything in it if no id is given. Returns how many were deleted
+pub fn purge(id: Option<&str>) -> Result<usize, String> {
+    let entries = match id {
+        Some(id) => vec![get(id)?],
+        None => list()?
+    };
+    for entry in &entries {
+        debug!("purging {} ({}) from trash", entry.id, entry.file_name);
+        std::fs::remove_dir_all(entry.get_dir()).map_err(|e| e.to_string())?;
+    }
+    Ok(entries.len())
+}
+
+/// Purges addons older than the max age, then the oldest until the trash fits in the max size. Returns how many were purged.
+/// Addons in keep are never purged, so an addon that was just deleted can always be restored
+pub fn enforce_retention(settings: &Settings, keep: &[String]) -> Result<usize, String> {
+    let max_age = settings.trash_max_age().as_secs();
+    let max_size = settings.trash_max_size();
+    let now = now();
+    let mut size = 0;
+    let mut purged = 0;
+    // Newest first, so anything past the size limit is the oldest
+    for entry in list()? {
+        size += entry.file_size;
+        if keep.contains(&entry.id) { continue; }
+        if now.saturating_sub(entry.deleted_at) > max_age || size > max_size {
+            debug!("purging {} ({}) from trash, past retention", entry.id, entry.file_name);
+            std::fs::remove_dir_all(entry.get_dir()).map_err(|e| e.to_string())?;
+            size -= entry.file_size;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use regex::Regex;
+    use crate::config::{set_test_appdir, Settings};
+    use crate::util::WORKSHOP_ID_REGEX;
+    use super::{enforce_retention, list, purge, restore, trash, TrashEntry};
+
+    /// Creates an empty addons folder, using its own app folder so the trash starts empty
+    fn addons_dir(name: &str) -> PathBuf {
+        // Set up by main
+        WORKSHOP_ID_REGEX.get_or_init(|| Regex::new(r"[0-9]{4,}").unwrap());
+        let dir = std::env::temp_dir()
+            .join(format!("l4d2-addon-manager-test-{}", std::process::id()))
+            .join(name);
+        std::fs::remove_dir_all(&dir).ok();
+        set_test_appdir(dir.join("appdir"));
+        let addons_dir = dir.join("addons");
+        std::fs::create_dir_all(&addons_dir).unwrap();
+        addons_dir
+    }
+
+    /// Changes when the entry was deleted
+    fn backdate(entry: &TrashEntry, secs: u64) {
+        let mut entry = entry.clone();
+        entry.deleted_at -= secs;
+        std::fs::write(entry.get_dir().join("trash.json"), serde_json::to_string(&entry).unwrap()).unwrap();
+    }
+
+    fn ids() -> Vec<String> {
+        list().unwrap().into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn same_name_gets_own_folder() {
+        let addons_dir = addons_dir("trash_same_name");
+        let path = addons_dir.join("addon.vpk");
+        std::fs::write(&path, b"first").unwrap();
+        let first = trash(&path).unwrap();
+        std::fs::write(&path, b"second").unwrap();
+        let second = trash(&path).unwrap();
+
+        assert_ne!(first.id, second.id);
+        assert_ne!(first.get_dir(), second.get_dir());
+        assert!(!path.exists());
+        assert_eq!(std::fs::read(first.get_path()).unwrap(), b"first");
+        assert_eq!(std::fs::read(second.get_path()).unwrap(), b"second");
+        assert_eq!(list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn restore_and_purge() {
+        let addons_dir = addons_dir("trash_restore");
+        let path = addons_dir.join("addon.vpk");
+        std::fs::write(&path, b"first").unwrap();
+        let first = trash(&path).unwrap();
+        std::fs::write(&path, b"second").unwrap();
+        let second = trash(&path).unwrap();
+
+        assert_eq!(restore(&first.id).unwrap(), path);
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        assert!(!first.get_dir().exists());
+        assert_eq!(ids(), std::slice::from_ref(&second.id));
+        // An addon with the same name is back in the folder
+        assert!(restore(&second.id).is_err());
+        assert!(second.get_path().exists());
+
+        assert_eq!(purge(Some(&second.id)).unwrap(), 1);
+        assert!(!second.get_dir().exists());
+        assert!(purge(Some(&second.id)).is_err());
+        assert!(list().unwrap().is_empty());
+
+        for name in ["a.vpk", "b.vpk"] {
+            std::fs::write(addons_dir.join(name), name).unwrap();
+            trash(&addons_dir.join(name)).unwrap();
+        }
+        assert_eq!(purge(None).unwrap(), 2);
+        assert!(list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retention_keeps_just_deleted() {
+        let addons_dir = addons_dir("trash_retention");
+        let mut entries = vec![];
+        for name in ["old.vpk", "big.vpk", "first.vpk", "second.vpk"] {
+            let path = addons_dir.join(name);
+            std::fs::write(&path, vec![0u8; 600 * 1024]).unwrap();
+            entries.push(trash(&path).unwrap());
+        }
+        let (old, big, first, second) = (&entries[0], &entries[1], &entries[2], &entries[3]);
+        backdate(old, 31 * 24 * 60 * 60);
+        backdate(big, 60);
+        let settings = Settings {
+            trash_max_size_mb: Some(1),
+            trash_max_age_days: Some(30),
+            ..Default::default()
+        };
+
+        // Both just deleted addons are kept, even though together they are over the size limit
+        assert_eq!(enforce_retention(&settings, &[first.id.clone(), second.id.clone()]).unwrap(), 2);
+        let mut kept = ids();
+        kept.sort();
+        let mut expected = vec![first.id.clone(), second.id.clone()];
+        expected.sort();
+        assert_eq!(kept, expected);
+
+        // Once they aren't just deleted, the older one goes
+        backdate(first, 30);
+        assert_eq!(enforce_retention(&settings, &[]).unwrap(), 1);
+        assert_eq!(ids(), std::slice::from_ref(&second.id));
+    }
+}
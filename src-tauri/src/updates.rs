@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use steam_workshop_api::WorkshopItem;
+use tauri::{AppHandle, Manager};
+use crate::config::SettingsManager;
+use crate::downloads::DownloadManager;
+use crate::steam::SteamApi;
+use crate::{cache, util};
+
+/// How often the scheduler wakes up to see if a check is due
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+
+/// An installed addon that has a newer version on the workshop
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AddonUpdate {
+    pub publishedfileid: u32,
+    pub file_path: PathBuf,
+    pub title: String,
+    /// time_updated of the cached workshop info, if it was cached
+    pub cached_time_updated: Option<u64>,
+    /// Modified time of the installed file
+    pub local_time_updated: Option<u64>,
+    /// time_updated of the latest workshop info
+    pub latest_time_updated: u64,
+    /// Size of the installed file
+    pub local_file_size: u64,
+    /// Size of the latest version on the workshop
+    pub latest_file_size: u64,
+    pub workshop_info: WorkshopItem
+}
+
+impl AddonUpdate {
+    /// In the workshop folder, where steam updates it on its own
+    pub fn is_steam_managed(&self) -> bool {
+        self.file_path.parent()
+            .and_then(|parent| parent.file_name())
+            .is_some_and(|name| name == "workshop")
+    }
+}
+
+struct InstalledAddon {
+    path: PathBuf,
+    modified: Option<u64>,
+    file_size: u64,
+    cached_time_updated: Option<u64>
+}
+
+/// Compares every workshop addon in the folders against the latest workshop info, returning the outdated ones
+pub fn check_updates(api: &SteamApi, dirs: &[&Path]) -> Result<Vec<AddonUpdate>, String> {
+    let mut installed: HashMap<u32, Vec<InstalledAddon>> = HashMap::new();
+    for dir in dirs {
+        if !dir.exists() { continue; }
+        for entry in util::get_vpks_in_folder(dir)? {
+            let path = entry.path();
+            let Some(id) = util::find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy()) else {
+                continue;
+            };
+            let meta = entry.metadata().map_err(|e| e.to_string())?;
+            installed.entry(id).or_default().push(InstalledAddon {
+                modified: meta.modified().ok().and_then(|s| s.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+                file_size: meta.len(),
+                cached_time_updated: cache::get_cached_workshop_info(&path, id).map(|item| item.time_updated as u64),
+                path
+            });
+        }
+    }
+
+    let ids: Vec<u32> = installed.keys().copied().collect();
+    debug!("checking {} workshop items for updates", ids.len());
+    let mut updates = vec![];
+    for item in util::fetch_workshop_items(api, &ids) {
+        let Ok(id) = item.publishedfileid.parse::<u32>() else { continue; };
+        let Some(addons) = installed.get(&id) else { continue; };
+        let latest_time_updated = item.time_updated as u64;
+        for addon in addons {
+            // The file's modified time is what tells us which version is installed,
+            // the cache only tells us when the info was last fetched
+            let Some(local_time) = addon.modified.or(addon.cached_time_updated) else { continue; };
+            if latest_time_updated > local_time {
+                updates.push(AddonUpdate {
+                    publishedfileid: id,
+                    file_path: addon.path.clone(),
+                    title: item.title.clone(),
+                    cached_time_updated: addon.cached_time_updated,
+                    local_time_updated: addon.modified,
+                    latest_time_updated,
+                    local_file_size: addon.file_size,
+                    latest_file_size: item.file_size.parse().unwrap_or(0),
+                    workshop_info: item.clone()
+                });
+            }
+        }
+    }
+    debug!("found {} outdated addons", updates.len());
+    Ok(updates)
+}
+
+/// Starts a thread that checks for updates every update_check_interval_minutes,
+/// sending an "updates-available" event with the outdated addons
+pub fn start_scheduler(app: AppHandle, settings: Arc<Mutex<SettingsManager>>, downloads: Arc<Mutex<DownloadManager>>) {
+    std::thread::spawn(move || {
+        loop {
+            if let Err(e) = run_scheduled_check(&app, &settings, &downloads) {
+                error!("Scheduled update check failed: {}", e);
+            }
+            std::thread::sleep(SCHEDULER_TICK);
+        }
+    });
+}
+
+fn run_scheduled_check(app: &AppHandle, settings: &Arc<Mutex<SettingsManager>>, downloads: &Arc<Mutex<DownloadManager>>) -> Result<(), String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // Only hold the lock long enough to copy what's needed, checks can take a while
+    let (dir, api, auto_update) = {
+        let settings = settings.lock().unwrap();
+        let config = settings.get();
+        let Some(interval) = config.update_check_interval_minutes else { return Ok(()) };
+        if let Some(last_check) = config.last_update_check {
+            if now < last_check + interval * 60 { return Ok(()) }
+        }
+        let Some(dir) = config.gamedir.clone() else { return Ok(()) };
+        (dir, SteamApi::new(config), config.auto_update)
+    };
+
+    debug!("running scheduled update check");
+    let workshop_dir = dir.join("workshop");
+    let updates = check_updates(&api, &[&dir, &workshop_dir])?;
+
+    {
+        let mut settings = settings.lock().unwrap();
+        settings.get_mut().last_update_check = Some(now);
+        settings.save()?;
+    }
+
+    if updates.is_empty() { return Ok(()) }
+    info!("scheduled check found {} outdated addons", updates.len());
+    if auto_update {
+        {
+            let mut downloads = downloads.lock().unwrap();
+            // Steam would overwrite anything queued in the workshop folder, so those are only reported
+            for update in updates.iter().filter(|update| !update.is_steam_managed()) {
+                if let Err(e) = downloads.add_item_to(update.workshop_info.clone(), update.file_path.clone()) {
+                    error!("Could not queue update for {}: {}", update.publishedfileid, e);
+                }
+            }
+        }
+        DownloadManager::process(downloads, app);
+    }
+    app.emit_all("updates-available", &updates).ok();
+    Ok(())
+}
@@ -1,6 +1,5 @@
-use std::collections::HashMap;
-use std::fs::{DirEntry, File};
-use std::io::{ErrorKind, Seek, SeekFrom};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::DirEntry;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
@@ -8,8 +7,14 @@ use std::time::UNIX_EPOCH;
 use log::{debug, error, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sourcepak::common::file::VPKFileReader;
-use steam_workshop_api::{SteamWorkshop, WorkshopItem};
+use steam_workshop_api::WorkshopItem;
+use crate::addonlist::{self, AddonState};
+use crate::cache::{self, CachedWorkshopInfo, WorkshopStatus};
+use crate::config::Settings;
+use crate::kv::KvObject;
+use crate::steam::{FileDetails, SteamApi};
+use crate::thumbnails::{self, Thumbnails};
+use crate::vpk::{self, Vpk};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct AddonEntry {
@@ -18,133 +23,249 @@ pub(crate) struct AddonEntry {
     file_size: u64,
     last_update_time: Option<u64>,
     create_time: Option<u64>,
+    item_type: ItemType,
+    /// Whether the addon is still on the workshop, None if it never was
+    workshop_status: Option<WorkshopStatus>,
+    /// Enabled state, as the game sees it
+    state: AddonState,
+    /// Local images extracted from the addon
+    thumbnails: Thumbnails,
 
     addon_data: Option<AddonData>,
 
     workshop_info: Option<WorkshopItem>
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ItemType {
+    /// Workshop has a newer version than the installed file
+    Updateable,
+    /// Installed in the addons folder, from the workshop
+    Managed,
+    /// Installed in the addons folder, not from the workshop
+    Unmanaged,
+    /// Named after a workshop id, but no workshop info could be found
+    Unknown,
+    /// Subscribed to, in the addons/workshop folder
+    Workshop
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AddonData {
     info: Option<AddonInfo>,
-    mission_info: Option<MissionInfo>
+    /// Every campaign in the addon
+    missions: Vec<MissionInfo>
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-// TODO: remove renames, use serde-aux to make field names case insensitive
+impl AddonData {
+    /// Image of every chapter in the mission, without duplicates
+    pub fn chapter_images(&self) -> Vec<String> {
+        let mut images: Vec<String> = self.missions.iter()
+            .flat_map(|mission| mission.modes.values())
+            .flatten()
+            .filter_map(|chapter| chapter.image.clone())
+            .collect();
+        images.sort();
+        images.dedup();
+        images
+    }
+}
+
+/// Keys of addoninfo.txt that have a field in AddonInfo, every other key is kept in AddonInfo::extra
+const ADDONINFO_KEYS: [&str; 24] = [
+    "addontitle", "addonversion", "addontagline", "addonauthor", "addonauthorsteamid", "addonsteamgroupname",
+    "addondescription", "addonurl0", "addonsteamappid",
+    "addoncontent_campaign", "addoncontent_map", "addoncontent_script", "addoncontent_survivor",
+    "addoncontent_bossinfected", "addoncontent_commoninfected", "addoncontent_weapon", "addoncontent_weaponmodel",
+    "addoncontent_skin", "addoncontent_spray", "addoncontent_prop", "addoncontent_prefab",
+    "addoncontent_music", "addoncontent_sound", "addoncontent_backgroundmovie"
+];
+
 // https://developer.valvesoftware.com/wiki/Addoninfo.txt
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct AddonInfo {
-    #[serde(rename(serialize = "title", deserialize = "addontitle"), alias = "addonTitle", alias = "AddonTitle")]
-    pub title: String,
-    #[serde(rename(serialize = "version", deserialize = "addonversion"), alias = "addonVersion", alias = "AddonVersion")]
+    pub title: Option<String>,
     pub version: Option<String>,
-    #[serde(rename(serialize = "author", deserialize = "addonauthor"), alias = "addonAuthor", alias = "AddonAuthor")]
+    pub tagline: Option<String>,
     pub author: Option<String>,
-    #[serde(rename(serialize = "description", deserialize = "addondescription"), alias = "addonDescription", alias = "AddonDescription")]
+    pub author_steam_id: Option<String>,
+    pub steam_group_name: Option<String>,
     pub description: Option<String>,
+    /// addonURL0, usually a link to the addon's page
+    pub url: Option<String>,
+    /// Game the addon is for, 550 for L4D2
+    pub steam_app_id: Option<u32>,
 
-    #[serde(rename(serialize = "content_script", deserialize = "addoncontent_script"), alias = "AddonContent_Script", default = "bool::default")]
-    pub content_script: bool,
-
-    #[serde(rename(serialize = "content_campaign", deserialize = "addoncontent_campaign"), alias = "addonContent_Campaign", default = "bool::default")]
     /** Has multiple maps that form a campaign? **/
     pub content_campaign: bool,
-
-    #[serde(rename(serialize = "content_map", deserialize = "addoncontent_map"), alias = "addonContent_Map", default = "bool::default")]
     /** Has at least one map file? **/
     pub content_map: bool,
-
-    #[serde(rename(serialize = "content_music", deserialize = "addoncontent_music"), alias = "addonContent_Music", default = "bool::default")]
+    pub content_script: bool,
+    pub content_survivor: bool,
+    pub content_boss_infected: bool,
+    pub content_common_infected: bool,
+    pub content_weapon: bool,
+    pub content_weapon_model: bool,
+    pub content_skin: bool,
+    pub content_spray: bool,
+    pub content_prop: bool,
+    pub content_prefab: bool,
     pub content_music: bool,
-
-    #[serde(rename(serialize = "content_sound", deserialize = "addoncontent_sound"), alias = "addonContent_Sound", default = "bool::default")]
     pub content_sound: bool,
+    /// Replaces the main menu's background movie
+    pub content_background_movie: bool,
 
-    #[serde(rename(serialize = "content_skin", deserialize = "addoncontent_skin"), alias = "addonContent_Skin", default = "bool::default")]
-    pub content_skin: bool,
-
-    #[serde(rename(serialize = "content_weapon", deserialize = "addoncontent_weapon"), alias = "addonContent_Weapon", default = "bool::default")]
-    pub content_weapon: bool
+    /// Keys not listed above, as they appear in the file
+    pub extra: BTreeMap<String, String>
+}
 
-    // TODO: add other categories
+impl AddonInfo {
+    /// Parses addoninfo.txt, ignoring the case of keys and accepting 0/1, true/false or yes/no for flags
+    pub fn parse(content: &str) -> Self {
+        let root = KvObject::parse(content);
+        // Some addons leave out the "AddonInfo" wrapper, or name it something else
+        let info = root.get_obj("AddonInfo")
+            .or_else(|| root.entries().find_map(|(_, value)| value.as_obj()))
+            .unwrap_or(&root);
+        let flag = |key: &str| info.get_bool(key).unwrap_or(false);
+        let extra = info.entries()
+            .filter(|(key, _)| !ADDONINFO_KEYS.iter().any(|known| known.eq_ignore_ascii_case(key)))
+            .filter_map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
+            .collect();
+        Self {
+            title: info.get_string("addontitle"),
+            version: info.get_string("addonversion"),
+            tagline: info.get_string("addontagline"),
+            author: info.get_string("addonauthor"),
+            author_steam_id: info.get_string("addonauthorsteamid"),
+            steam_group_name: info.get_string("addonsteamgroupname"),
+            description: info.get_string("addondescription"),
+            url: info.get_string("addonurl0"),
+            steam_app_id: info.get_str("addonsteamappid").and_then(|id| id.parse().ok()),
+
+            content_campaign: flag("addoncontent_campaign"),
+            content_map: flag("addoncontent_map"),
+            content_script: flag("addoncontent_script"),
+            content_survivor: flag("addoncontent_survivor"),
+            content_boss_infected: flag("addoncontent_bossinfected"),
+            content_common_infected: flag("addoncontent_commoninfected"),
+            content_weapon: flag("addoncontent_weapon"),
+            content_weapon_model: flag("addoncontent_weaponmodel"),
+            content_skin: flag("addoncontent_skin"),
+            content_spray: flag("addoncontent_spray"),
+            content_prop: flag("addoncontent_prop"),
+            content_prefab: flag("addoncontent_prefab"),
+            content_music: flag("addoncontent_music"),
+            content_sound: flag("addoncontent_sound"),
+            content_background_movie: flag("addoncontent_backgroundmovie"),
+
+            extra
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MissionInfo {
-    modes: Option<MissionModes>
-}
-#[derive(Serialize, Deserialize, Clone)]
-pub struct MissionModes {
-    coop: Option<HashMap<u64, MissionChapter>>,
-    versus: Option<HashMap<u64, MissionChapter>>,
-    survival: Option<HashMap<u64, MissionChapter>>,
+    /// Path of the mission file in the vpk
+    pub file: String,
+    pub name: Option<String>,
+    pub display_title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub website: Option<String>,
+    pub version: Option<String>,
+    /// Chapters of every mode the mission supports, in order. Keyed by the mode's lowercase name,
+    /// such as coop, versus, survival, scavenge, realism or a mutation
+    pub modes: BTreeMap<String, Vec<MissionChapter>>
 }
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MissionChapter {
-    #[serde(rename(serialize = "map", deserialize = "Map"))]
-    map: String,
-    #[serde(rename(serialize = "display_name", deserialize = "DisplayName"))]
-    display_name: String,
-    #[serde(rename(serialize = "image", deserialize = "Image"))]
-    image: String,
+    pub number: u64,
+    pub map: String,
+    pub display_name: Option<String>,
+    pub image: Option<String>
 }
 
-use sourcepak::pak::v1::format::VPKVersion1;
-pub fn get_addon_data(path: &Path) -> Result<AddonData, String> {
-    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
-    let vpk = VPKVersion1::try_from(&mut file)?;
-    // debug!("archive_path={} file_name={} v={} tree_size={} #files={}", archive_path, archive_file_name, vpk.header.tree_size, vpk.header.tree_size, vpk.tree.files.len());
-    let addoninfo = vpk.tree.files.get(" /addoninfo.txt");
-    if let Some(addoninfo) = addoninfo {
-        let offset_start = file.stream_position().unwrap();
-        // debug!("found addoninfo.txt. offset={} len={} i={}", addoninfo.entry_offset, addoninfo.entry_length, addoninfo.archive_index);
-        file.seek(SeekFrom::Current(addoninfo.entry_offset as i64)).expect("seek failed");
-
-        let buf = file.read_bytes(addoninfo.entry_length as usize).unwrap();
-        let content = String::from_utf8_lossy(&buf);
-        let addon_info: AddonInfo = keyvalues_serde::from_str(&content)
-            .map_err(|e| format!("failed to parse addoninfo.txt: {}", e))?;
-
-        file.seek(SeekFrom::Start(offset_start)).unwrap();
-        let mission_info = get_mission_data(&mut file, &vpk);
-
-        Ok(AddonData {
-            info: Some(addon_info),
-            mission_info,
+impl MissionInfo {
+    pub fn parse(file: &str, content: &str) -> Result<Self, String> {
+        let root = KvObject::parse(content);
+        let mission = root.get_obj("mission")
+            .ok_or_else(|| "No \"mission\" section".to_string())?;
+        let mut modes: BTreeMap<String, Vec<MissionChapter>> = BTreeMap::new();
+        for (mode, chapters) in mission.get_obj("modes").map(|modes| modes.entries()).into_iter().flatten() {
+            let Some(chapters) = chapters.as_obj() else { continue; };
+            let mut chapters: Vec<MissionChapter> = chapters.entries()
+                .filter_map(|(number, chapter)| {
+                    let chapter = chapter.as_obj()?;
+                    Some(MissionChapter {
+                        number: number.trim().parse().ok()?,
+                        map: chapter.get_string("Map")?,
+                        display_name: chapter.get_string("DisplayName"),
+                        image: chapter.get_string("Image")
+                    })
+                })
+                .collect();
+            chapters.sort_by_key(|chapter| chapter.number);
+            if !chapters.is_empty() {
+                modes.entry(mode.to_lowercase()).or_insert(chapters);
+            }
+        }
+        Ok(Self {
+            file: file.to_string(),
+            name: mission.get_string("Name"),
+            display_title: mission.get_string("DisplayTitle"),
+            description: mission.get_string("Description"),
+            author: mission.get_string("Author"),
+            website: mission.get_string("Website"),
+            version: mission.get_string("Version"),
+            modes
         })
-    } else {
-        // TODO: make just a warning, return Option<?>
-        Err("No addoninfo.txt found".to_string())
     }
 }
-pub fn get_workshop_info(ws: &SteamWorkshop, publishedfileid: u32) -> Result<Option<WorkshopItem>, String> {
-    let entries = vec![publishedfileid.to_string()];
-    let mut latest_info = ws.get_published_file_details(&entries)
-        .map_err(|e| e.to_string())?;
-    if latest_info.len() == 0 {
-        // TODO: mark this in the cache, that it's deleted?
+
+pub fn get_addon_data(path: &Path) -> Result<AddonData, String> {
+    let mut vpk = Vpk::open(path)?;
+    let addoninfo_path = vpk.tree_paths().into_iter()
+        .find(|tree_path| vpk::normalize_path(tree_path) == "addoninfo.txt")
+        // TODO: make just a warning, return Option<?>
+        .ok_or_else(|| "No addoninfo.txt found".to_string())?;
+    let buf = vpk.read(&addoninfo_path)?;
+    let content = String::from_utf8_lossy(&buf);
+    let addon_info = AddonInfo::parse(&content);
+
+    Ok(AddonData {
+        info: Some(addon_info),
+        missions: get_missions(&mut vpk),
+    })
+}
+pub fn get_workshop_info(api: &SteamApi, publishedfileid: u32) -> Result<Option<WorkshopItem>, String> {
+    let mut latest_info = api.get_published_file_details(&[publishedfileid])?;
+    if latest_info.is_empty() {
+        // Callers that know the addon's path record this with cache::save_missing_workshop_info
         return Ok(None)
     }
-    let latest_info = latest_info.remove(0);
-    return Ok(Some(latest_info))
+    Ok(Some(latest_info.remove(0)))
 }
 
-pub fn get_mission_data(file: &mut File, vpk: &VPKVersion1) -> Option<MissionInfo> {
-    for (path, entry) in &vpk.tree.files {
-        if path.starts_with("missions/") && path.ends_with(".txt") {
-            file.seek(SeekFrom::Current(entry.entry_offset as i64)).unwrap();
-            let buf = file.read_bytes(entry.entry_length as usize).unwrap();
-            let content = String::from_utf8_lossy(&buf);
-            return match keyvalues_serde::from_str::<MissionInfo>(&content) {
-                Ok(mi) => Some(mi),
-                Err(e) => {
-                    error!("Failed to parse mission file = {}", e);
-                    None
-                }
+/// Parses every missions/*.txt in the vpk, skipping any that are not valid
+pub fn get_missions(vpk: &mut Vpk) -> Vec<MissionInfo> {
+    let mut paths: Vec<String> = vpk.tree_paths().into_iter()
+        .filter(|tree_path| {
+            let path = vpk::normalize_path(tree_path);
+            path.starts_with("missions/") && path.ends_with(".txt")
+        })
+        .collect();
+    paths.sort();
+    paths.iter().filter_map(|tree_path| {
+        let file = vpk::normalize_path(tree_path);
+        match vpk.read(tree_path).and_then(|buf| MissionInfo::parse(&file, &String::from_utf8_lossy(&buf))) {
+            Ok(mission) => Some(mission),
+            Err(e) => {
+                error!("Failed to parse mission file {} = {}", file, e);
+                None
             }
         }
-    }
-    None
+    }).collect()
 }
 
 pub fn prompt_game_dir() -> PathBuf {
@@ -166,14 +287,14 @@ pub fn prompt_game_dir() -> PathBuf {
             std::fs::create_dir_all(meta_path).ok();
             return path;
         }
-        return path
+        path
     } else {
         eprintln!("Could not open file dialog");
         std::process::exit(1);
     }
 }
 
-fn get_vpks_in_folder(path: &Path) -> Result<Vec<DirEntry>, String> {
+pub(crate) fn get_vpks_in_folder(path: &Path) -> Result<Vec<DirEntry>, String> {
     let entries = std::fs::read_dir(path).map_err(|e| e.to_string())?;
     let mut files: Vec<DirEntry> = Vec::new();
     for entry in entries {
@@ -189,12 +310,17 @@ fn get_vpks_in_folder(path: &Path) -> Result<Vec<DirEntry>, String> {
 }
 
 pub(crate) struct WorkshopResult {
-    item: WorkshopItem,
+    item: Option<WorkshopItem>,
+    status: WorkshopStatus,
     cached: bool
 }
 
-pub(crate) fn get_workshop_data(ws: &SteamWorkshop, entries: &[DirEntry]) -> HashMap<u32, WorkshopResult> {
+pub(crate) fn get_workshop_data(settings: &Settings, entries: &[DirEntry]) -> HashMap<u32, WorkshopResult> {
+    let ttl = settings.workshop_cache_ttl();
     let mut pending_workshop_ids: Vec<u32> = vec![];
+    let mut pending_paths: HashMap<u32, Vec<PathBuf>> = HashMap::new();
+    // Expired entries, used if steam can't be reached
+    let mut stale: HashMap<u32, CachedWorkshopInfo> = HashMap::new();
     let mut results: HashMap<u32, WorkshopResult> = HashMap::with_capacity(entries.len());
     for entry in entries {
         let path = entry.path();
@@ -202,36 +328,92 @@ pub(crate) fn get_workshop_data(ws: &SteamWorkshop, entries: &[DirEntry]) -> Has
         let workshop_id = find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy());
         if workshop_id.is_none() { continue; }
         let workshop_id = workshop_id.unwrap();
-        if let Some(item) = get_cached_workshop_info(&path, workshop_id) {
-            results.insert(workshop_id, WorkshopResult { item, cached: true });
-        } else {
-            // Queue up for bulk fetching
-            pending_workshop_ids.push(workshop_id);
+        match cache::read_cache(&path, workshop_id) {
+            Some(cached) if !cached.is_expired(ttl) => {
+                results.insert(workshop_id, WorkshopResult { item: cached.item, status: cached.status, cached: true });
+            },
+            cached => {
+                if let Some(cached) = cached {
+                    stale.insert(workshop_id, cached);
+                }
+                // Queue up for bulk fetching
+                pending_workshop_ids.push(workshop_id);
+                pending_paths.entry(workshop_id).or_default().push(path);
+            }
         }
     }
 
+    let details = fetch_workshop_details(&SteamApi::new(settings), &pending_workshop_ids);
+    for item in details.items {
+        results.insert(item.publishedfileid.parse().unwrap(), WorkshopResult { item: Some(item), status: WorkshopStatus::Live, cached: false });
+    }
+
+    // Anything steam reported on but did not return has been removed or made private.
+    // Ids without a status were in a failed batch, so they fall back to the stale entry
+    for id in pending_workshop_ids {
+        if results.contains_key(&id) { continue; }
+        let Some(&status) = details.statuses.get(&id) else { continue; };
+        if status == WorkshopStatus::Live { continue; }
+        for path in &pending_paths[&id] {
+            let cached = cache::save_missing_workshop_info(path, id, status);
+            results.insert(id, WorkshopResult { item: cached.item, status, cached: true });
+        }
+    }
+    for (id, cached) in stale {
+        results.entry(id).or_insert(WorkshopResult { item: cached.item, status: cached.status, cached: true });
+    }
+
+    results
+}
+
+/// Fetches the latest workshop details of all ids, skipping any batches that fail.
+/// Ids from failed batches have no status, as there is no way to tell if they are missing
+pub fn fetch_workshop_details(api: &SteamApi, ids: &[u32]) -> FileDetails {
+    let mut results = FileDetails::default();
     // Steam API only accepts 100 entries at a time
-    while pending_workshop_ids.len() > 0 {
-        // let items = steam_workshop_api::Workshop::
-        let end = pending_workshop_ids.len().min(100);
-        let slice: Vec<u32> = pending_workshop_ids.drain(0..end).collect();
-        let slice: Vec<String> = slice.iter().map(|d| d.to_string()).collect();
-        debug!("slice = {}", slice.join(" "));
-        match ws.get_published_file_details(&slice) {
-            Ok(items) => {
-                for item in items {
-                    results.insert(item.publishedfileid.parse().unwrap(), WorkshopResult { item, cached: false });
-                }
+    for chunk in ids.chunks(100) {
+        debug!("slice = {:?}", chunk);
+        match api.get_file_details(chunk) {
+            Ok(details) => {
+                results.items.extend(details.items);
+                results.statuses.extend(details.statuses);
             },
             Err(e) => {
-                error!("get_workshop_data error: {}", e)
+                error!("fetch_workshop_details error: {}", e)
             }
         }
-
     }
-
     results
 }
+
+/// Fetches the latest workshop details of all ids, skipping any batches that fail
+pub fn fetch_workshop_items(api: &SteamApi, ids: &[u32]) -> Vec<WorkshopItem> {
+    fetch_workshop_details(api, ids).items
+}
+
+/// Works out what kind of addon the file is, based on its folder and workshop info
+pub fn get_item_type(path: &Path, workshop_info: Option<&WorkshopItem>, modified: Option<u64>) -> ItemType {
+    let in_workshop_folder = path.parent()
+        .and_then(|p| p.file_name())
+        .is_some_and(|name| name == "workshop");
+    let has_workshop_id = path.file_stem()
+        .and_then(|stem| find_workshop_id_in_str(&stem.to_string_lossy()))
+        .is_some();
+    match (workshop_info, modified) {
+        (Some(item), Some(modified)) if item.time_updated as u64 > modified => ItemType::Updateable,
+        (_, _) if in_workshop_folder => ItemType::Workshop,
+        (Some(_), _) => ItemType::Managed,
+        // Named like a workshop item, but steam had nothing on it
+        (None, _) if has_workshop_id => ItemType::Unknown,
+        (None, _) => ItemType::Unmanaged
+    }
+}
+/// Status for files named after a workshop id, Unknown if steam's status couldn't be found
+fn get_workshop_status(path: &Path, status: Option<WorkshopStatus>) -> Option<WorkshopStatus> {
+    path.file_stem()
+        .and_then(|stem| find_workshop_id_in_str(&stem.to_string_lossy()))
+        .map(|_| status.unwrap_or(WorkshopStatus::Unknown))
+}
 /// Gets addon info, assuming that the workshop information is already cached
 pub fn get_addon_info(path: &Path) -> Result<AddonEntry, String> {
     if !path.exists() || path.is_dir() {
@@ -239,28 +421,44 @@ pub fn get_addon_info(path: &Path) -> Result<AddonEntry, String> {
     }
     let meta = path.metadata().unwrap();
 
-    let addon_data: Option<AddonData> = get_addon_data(&path).ok();
+    let addon_data: Option<AddonData> = get_addon_data(path).ok();
     // We assume that the addon _should_ be cached
-    let workshop_info = find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy())
-        .and_then(|id| get_cached_workshop_info(path, id));
+    let cached = find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy())
+        .and_then(|id| cache::read_cache(path, id));
+    let workshop_status = get_workshop_status(path, cached.as_ref().map(|c| c.status));
+    let workshop_info = cached.and_then(|c| c.item);
+
+    let last_update_time = meta.modified().ok().map(|s| s.duration_since(UNIX_EPOCH).unwrap().as_secs());
+    let addon_list = addonlist::load_for_folder(path.parent().unwrap());
+    let chapter_images = addon_data.as_ref().map(|data| data.chapter_images()).unwrap_or_default();
 
     Ok(AddonEntry {
         file_path: path.to_string_lossy().to_string(),
         file_name: path.file_name().unwrap().to_string_lossy().to_string(),
         file_size: meta.size(),
-        last_update_time: meta.modified().ok().and_then(|s| Some(s.duration_since(UNIX_EPOCH).unwrap().as_secs())),
-        create_time: meta.created().ok().and_then(|s| Some(s.duration_since(UNIX_EPOCH).unwrap().as_secs())),
+        last_update_time,
+        create_time: meta.created().ok().map(|s| s.duration_since(UNIX_EPOCH).unwrap().as_secs()),
+        item_type: get_item_type(path, workshop_info.as_ref(), last_update_time),
+        workshop_status,
+        state: addonlist::get_addon_state(addon_list.as_ref(), path),
+        thumbnails: thumbnails::get_thumbnails(path, &chapter_images),
 
         workshop_info,
         addon_data
     })
 }
-pub fn get_addons(workshop: &SteamWorkshop, dir: &Path) -> Result<Vec<AddonEntry>, String> {
+pub fn get_addons(settings: &Settings, dir: &Path) -> Result<Vec<AddonEntry>, String> {
     let entries = get_vpks_in_folder(dir)?;
-    let mut workshop_record = get_workshop_data(workshop, &entries);
+    let mut workshop_record = get_workshop_data(settings, &entries);
+    if let Err(e) = cache::remove_orphaned(dir) {
+        warn!("Could not clean up cache in {:?}: {}", dir, e);
+    }
+    if let Err(e) = thumbnails::remove_orphaned(dir) {
+        warn!("Could not clean up thumbnails in {:?}: {}", dir, e);
+    }
+    let addon_list = addonlist::load_for_folder(dir);
     let mut files: Vec<AddonEntry> = vec![];
 
-
     for entry in entries {
         let meta = entry.metadata().unwrap();
         let path = entry.path();
@@ -269,25 +467,23 @@ pub fn get_addons(workshop: &SteamWorkshop, dir: &Path) -> Result<Vec<AddonEntry
         let workshop_info = find_workshop_id_in_str(&path.file_stem().unwrap().to_string_lossy())
             .and_then(|id| workshop_record.remove(&id));
         // If item was not cached, then save to file
-        if let Some(data) = &workshop_info {
-            if !data.cached {
-                let content = serde_json::to_string(&data.item).unwrap();
-                let mut path = path.parent().unwrap().join(".addon_manager/");
-                if !path.exists() {
-                    std::fs::create_dir(&path).ok();
-                }
-                path.push(format!("{}.json", data.item.publishedfileid));
-                debug!("writing cache to {:?}", &path);
-                std::fs::write(path, content).ok();
-            }
+        if let Some(WorkshopResult { item: Some(item), cached: false, .. }) = &workshop_info {
+            cache::save_cached_workshop_info(&path, item);
         }
-        let workshop_info = workshop_info.map(|data| data.item);
+        let workshop_status = get_workshop_status(&path, workshop_info.as_ref().map(|data| data.status));
+        let workshop_info = workshop_info.and_then(|data| data.item);
+        let last_update_time = meta.modified().ok().map(|s| s.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        let chapter_images = addon_data.as_ref().map(|data| data.chapter_images()).unwrap_or_default();
         let file = AddonEntry {
             file_path: entry.path().to_string_lossy().to_string(),
             file_name: entry.file_name().to_str().unwrap().to_string(),
             file_size: meta.size(),
-            last_update_time: meta.modified().ok().and_then(|s| Some(s.duration_since(UNIX_EPOCH).unwrap().as_secs())),
-            create_time: meta.created().ok().and_then(|s| Some(s.duration_since(UNIX_EPOCH).unwrap().as_secs())),
+            last_update_time,
+            create_time: meta.created().ok().map(|s| s.duration_since(UNIX_EPOCH).unwrap().as_secs()),
+            item_type: get_item_type(&path, workshop_info.as_ref(), last_update_time),
+            workshop_status,
+            state: addonlist::get_addon_state(addon_list.as_ref(), &path),
+            thumbnails: thumbnails::get_thumbnails(&path, &chapter_images),
 
             workshop_info,
             addon_data
@@ -302,17 +498,127 @@ pub fn find_workshop_id_in_str(file_name: &str) -> Option<u32> {
     WORKSHOP_ID_REGEX.get().unwrap().captures(file_name)
         .map(|c| c[0].parse().unwrap())
 }
-pub fn get_cached_workshop_info(path: &Path, workshop_id: u32) -> Option<WorkshopItem> {
-    let path = path.parent()?.join(".addon_manager").join(format!("{}.json", workshop_id));
-    match std::fs::read_to_string(&path) {
-        Ok(content) => {
-            serde_json::from_str(&content).ok()
-        },
-        Err(e) => {
-            if e.kind() != ErrorKind::NotFound {
-                warn!("Could not read cached workshop info at {:?}: {}", path, e);
-            }
-            None
+/// Returns the path the addon would have after being enabled or disabled
+pub fn get_toggled_path(path: &Path) -> Result<PathBuf, String> {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    if file_name.ends_with(".disabled") {
+        // Remove the .disabled:
+        Ok(path.with_extension(""))
+    } else if file_name.ends_with(".vpk") {
+        // Add on .disabled:
+        Ok(path.with_file_name(format!("{}.disabled", file_name)))
+    } else {
+        Err("Filename does not end with .disabled or .vpk, cannot toggle".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::vpk::{write_test_vpk, Vpk};
+    use super::{get_missions, AddonInfo, MissionInfo};
+
+    /// Writes a test vpk with the name into the temp folder
+    fn write_vpk(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("l4d2-addon-manager-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        write_test_vpk(&path, files);
+        path
+    }
+
+    #[test]
+    fn parses_every_mode() {
+        let mission = MissionInfo::parse("missions/modes.txt", include_str!("../tests/fixtures/missions/modes.txt")).unwrap();
+        assert_eq!(mission.name.as_deref(), Some("deadbeat"));
+        assert_eq!(mission.display_title.as_deref(), Some("Deadbeat Escape"));
+        assert_eq!(mission.author.as_deref(), Some("Mapper"));
+        assert_eq!(mission.version.as_deref(), Some("3"));
+        let modes: Vec<&str> = mission.modes.keys().map(|mode| mode.as_str()).collect();
+        assert_eq!(modes, ["coop", "mutation12", "realism", "scavenge", "survival", "versus"]);
+        let coop: Vec<&str> = mission.modes["coop"].iter().map(|chapter| chapter.map.as_str()).collect();
+        assert_eq!(coop, ["db_01", "db_02"]);
+        assert_eq!(mission.modes["coop"][0].display_name.as_deref(), Some("The Town"));
+        assert_eq!(mission.modes["coop"][0].image.as_deref(), Some("maps/db_01"));
+    }
+
+    #[test]
+    fn parses_hand_written_quirks() {
+        let mission = MissionInfo::parse("missions/quirks.txt", include_str!("../tests/fixtures/missions/quirks.txt")).unwrap();
+        assert_eq!(mission.name.as_deref(), Some("quirks"));
+        assert_eq!(mission.display_title.as_deref(), Some("PC Title"));
+        assert_eq!(mission.description, None);
+        assert_eq!(mission.author.as_deref(), Some("C:\\new\\tools"));
+        let chapter = &mission.modes["coop"][0];
+        assert_eq!(chapter.map, "qk_01");
+        assert_eq!(chapter.image.as_deref(), Some("maps\\qk_01\\"));
+        assert_eq!(chapter.display_name.as_deref(), Some("After The Backslash"));
+    }
+
+    #[test]
+    fn reads_every_mission_file_in_vpk() {
+        let path = write_vpk("missions.vpk", &[
+            ("addoninfo.txt", b"\"AddonInfo\" { \"addontitle\" \"Deadbeat\" }"),
+            ("missions/second.txt", include_bytes!("../tests/fixtures/missions/second.txt")),
+            ("missions/modes.txt", include_bytes!("../tests/fixtures/missions/modes.txt")),
+            ("missions/broken.txt", b"\"notamission\" {}"),
+            ("maps/db_01.txt", b"\"mission\" {}")
+        ]);
+        let missions = get_missions(&mut Vpk::open(&path).unwrap());
+        std::fs::remove_file(&path).ok();
+        let files: Vec<&str> = missions.iter().map(|mission| mission.file.as_str()).collect();
+        assert_eq!(files, ["missions/modes.txt", "missions/second.txt"]);
+        assert_eq!(missions[1].name.as_deref(), Some("deadbeat_extra"));
+    }
+
+    #[test]
+    fn addoninfo_keys_ignore_case() {
+        let info = AddonInfo::parse(include_str!("../tests/fixtures/addoninfo/mixed_case.txt"));
+        assert_eq!(info.title.as_deref(), Some("Mixed Case"));
+        assert_eq!(info.version.as_deref(), Some("1.2"));
+        assert_eq!(info.tagline.as_deref(), Some("Keys in every case"));
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert_eq!(info.url.as_deref(), Some("https://example.com"));
+        assert_eq!(info.steam_app_id, Some(550));
+        assert!(info.content_campaign);
+        assert!(info.extra.is_empty());
+        for key in ["addonTitle", "ADDONTITLE", "AddonTitle"] {
+            let info = AddonInfo::parse(&format!("\"AddonInfo\" {{ \"{}\" \"Title\" }}", key));
+            assert_eq!(info.title.as_deref(), Some("Title"), "{}", key);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn addoninfo_flags() {
+        let info = AddonInfo::parse(include_str!("../tests/fixtures/addoninfo/flags.txt"));
+        assert!(!info.content_campaign);
+        assert!(info.content_map);
+        assert!(info.content_script);
+        assert!(info.content_survivor);
+        assert!(!info.content_weapon);
+        assert!(!info.content_skin);
+        assert!(!info.content_sound);
+        assert!(info.content_background_movie);
+        assert!(info.extra.is_empty());
+    }
+
+    #[test]
+    fn addoninfo_without_wrapper() {
+        let info = AddonInfo::parse(include_str!("../tests/fixtures/addoninfo/no_wrapper.txt"));
+        assert_eq!(info.title.as_deref(), Some("No Wrapper"));
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert!(info.content_map);
+    }
+
+    #[test]
+    fn addoninfo_unquoted_with_comments() {
+        let info = AddonInfo::parse(include_str!("../tests/fixtures/addoninfo/unquoted.txt"));
+        assert_eq!(info.title.as_deref(), Some("Unquoted Values"));
+        assert_eq!(info.version.as_deref(), Some("2.0"));
+        assert_eq!(info.author.as_deref(), Some("Someone"));
+        assert!(info.content_survivor);
+        assert!(!info.content_prop);
+        let extra: Vec<(&str, &str)> = info.extra.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(extra, [("AddonMinVersion", "1.0.0.0"), ("customkey", "value")]);
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sourcepak::common::file::VPKFileReader;
+use sourcepak::pak::v1::format::VPKVersion1;
+
+/// A file inside a vpk
+#[derive(Serialize, Deserialize, Clone)]
+pub struct VpkFile {
+    /// Path inside the vpk, such as scripts/vscripts/mapspawn.nut
+    pub path: String,
+    pub size: u64
+}
+
+/// An opened vpk, with the position its file data starts at
+pub struct Vpk {
+    file: File,
+    data_start: u64,
+    pub vpk: VPKVersion1
+}
+
+impl Vpk {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let vpk = VPKVersion1::try_from(&mut file)?;
+        // Addon vpks store their data right after the tree, entry offsets are relative to it
+        let data_start = file.stream_position().map_err(|e| e.to_string())?;
+        Ok(Self { file, data_start, vpk })
+    }
+
+    /// Reads the contents of a file in the vpk, by its path in the tree
+    pub fn read(&mut self, tree_path: &str) -> Result<Vec<u8>, String> {
+        let entry = self.vpk.tree.files.get(tree_path)
+            .ok_or_else(|| format!("No file {} in vpk", tree_path))?;
+        let (offset, length) = (entry.entry_offset as u64, entry.entry_length as usize);
+        // The start of a file can be stored in the tree as preload data, the rest follows in the data section
+        let mut content = match entry.preload_length {
+            0 => vec![],
+            _ => self.vpk.tree.preload.get(tree_path).cloned().unwrap_or_default()
+        };
+        if length > 0 {
+            self.file.seek(SeekFrom::Start(self.data_start + offset)).map_err(|e| e.to_string())?;
+            content.extend(self.file.read_bytes(length).map_err(|e| e.to_string())?);
+        }
+        Ok(content)
+    }
+
+    /// Tree path of every file, in the tree's order
+    pub fn tree_paths(&self) -> Vec<String> {
+        self.vpk.tree.files.keys().cloned().collect()
+    }
+}
+
+/// Path of a file in a vpk's tree as the game sees it, lowercase with forward slashes.
+/// sourcepak keys files at the root of the vpk as " /name.ext"
+pub fn normalize_path(tree_path: &str) -> String {
+    get_display_path(tree_path).to_lowercase()
+}
+
+/// Path of a file in a vpk's tree, keeping its case
+fn get_display_path(tree_path: &str) -> String {
+    tree_path.trim_start_matches(" /").replace('\\', "/")
+}
+
+/// Every file path in the vpk, normalized
+pub fn get_file_paths(path: &Path) -> Result<Vec<String>, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let vpk = VPKVersion1::try_from(&mut file)?;
+    Ok(vpk.tree.files.keys().map(|key| normalize_path(key)).collect())
+}
+
+/// Every file in the vpk with its size, sorted by path
+pub fn list_files(path: &Path) -> Result<Vec<VpkFile>, String> {
+    let vpk = Vpk::open(path)?;
+    let mut files: Vec<VpkFile> = vpk.vpk.tree.files.iter()
+        .map(|(tree_path, entry)| VpkFile {
+            path: get_display_path(tree_path),
+            size: entry.preload_length as u64 + entry.entry_length as u64
+        })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Extracts every file matching the glob, such as "missions/*.txt" or "scripts/**/*.nut", into dest keeping
+/// their folders. A plain path extracts just that file. Returns the paths written
+pub fn extract(path: &Path, pattern: &str, dest: &Path) -> Result<Vec<PathBuf>, String> {
+    let pattern = Pattern::new(pattern.replace('\\', "/").trim_start_matches('/'))
+        .map_err(|e| format!("Invalid pattern: {}", e))?;
+    let options = MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: true,
+        require_literal_leading_dot: false
+    };
+    let mut vpk = Vpk::open(path)?;
+    let mut written = vec![];
+    for tree_path in vpk.tree_paths() {
+        let file_path = get_display_path(&tree_path);
+        if !pattern.matches_with(&file_path, options) { continue; }
+        // Don't let a crafted vpk write outside of dest
+        if file_path.starts_with('/') || file_path.split('/').any(|part| part == ".." || part.contains(':')) {
+            return Err(format!("Refusing to extract {}, its path leaves the destination", file_path));
+        }
+        let out_path = dest.join(&file_path);
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = vpk.read(&tree_path)?;
+        std::fs::write(&out_path, content)
+            .map_err(|e| format!("Could not write {:?}: {}", out_path, e))?;
+        written.push(out_path);
+    }
+    debug!("extracted {} files from {:?} to {:?}", written.len(), path, dest);
+    if written.is_empty() {
+        return Err(format!("No files in the vpk match \"{}\"", pattern));
+    }
+    Ok(written)
+}
+
+/// Writes a single file vpk holding the files, with no preload data
+#[cfg(test)]
+pub(crate) fn write_test_vpk(path: &Path, files: &[(&str, &[u8])]) {
+    let mut tree: Vec<u8> = vec![];
+    let mut data: Vec<u8> = vec![];
+    for (file_path, content) in files {
+        let (dir, file) = file_path.rsplit_once('/').unwrap_or((" ", *file_path));
+        let (stem, ext) = file.rsplit_once('.').unwrap();
+        // One extension and folder per file keeps it simple, the format allows repeating them
+        for part in [ext, dir, stem] {
+            tree.extend(part.as_bytes());
+            tree.push(0);
+        }
+        tree.extend(0u32.to_le_bytes()); // crc
+        tree.extend(0u16.to_le_bytes()); // preload bytes
+        tree.extend(0x7fffu16.to_le_bytes()); // data is in this file
+        tree.extend((data.len() as u32).to_le_bytes());
+        tree.extend((content.len() as u32).to_le_bytes());
+        tree.extend(0xffffu16.to_le_bytes());
+        tree.extend([0, 0]); // end of files, end of folders
+        data.extend(*content);
+    }
+    tree.push(0); // end of extensions
+    let mut vpk = vec![];
+    vpk.extend(0x55aa1234u32.to_le_bytes());
+    vpk.extend(1u32.to_le_bytes());
+    vpk.extend((tree.len() as u32).to_le_bytes());
+    vpk.extend(tree);
+    vpk.extend(data);
+    std::fs::write(path, vpk).unwrap();
+}